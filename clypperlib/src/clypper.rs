@@ -0,0 +1,523 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+    thread::{self, JoinHandle},
+};
+#[cfg(feature = "whisper")]
+use std::path::Path;
+
+use crate::{
+    download::{cut::CutMode, ffmpeg::{FFmpeg, FFmpegControl, FFmpegOutput, FFmpegState}, installation::FFmpegInstallation, layout::{LayoutStore, LayoutTemplate}, loudness::LoudnessTarget, profile::{codec_name, EncodeProfile}, progress::ProgressReport, reframe::Reframe, subtitles::{CaptionStyle, SubtitleFormat, DEFAULT_PLAY_RES}, verify::Verification},
+    error::ClypperError,
+    extract::{captions::{find_track, Captions}, extractor::{Clip, ClipResource, ClipTime, Segment}, format::FormatSelector, http::HttpClient, metadata::ClipMetadata, registry::ExtractorRegistry},
+};
+#[cfg(feature = "whisper")]
+use crate::download::transcribe::Transcriber;
+
+const DEFAULT_FILE_NAME: &str = "{channel} - {title}";
+///Longer than the GOPs youtube and twitch use
+const STREAM_COPY_TOLERANCE_MS: u64 = 10_000;
+
+type ProgressCallback = Box<dyn Fn(ProgressReport) + Send>;
+type StateChangeCallback = Box<dyn Fn(FFmpegState) + Send>;
+
+///
+/// Describes a single clip to download: where it comes from, where it goes and how to report
+/// on it while it runs.
+///
+pub struct ClipRequest{
+    url: String,
+    output: PathBuf,
+    segment: Option<Segment>,
+    format: FormatSelector,
+    profile: EncodeProfile,
+    cut_mode: CutMode,
+    reframe: Option<Reframe>,
+    layout: Option<LayoutTemplate>,
+    ///Language and whether auto-generated tracks will do
+    captions: Option<(String, bool)>,
+    burn_captions: Option<CaptionStyle>,
+    export_captions: Option<SubtitleFormat>,
+    ///The whisper model to make captions with
+    #[cfg(feature = "whisper")]
+    transcribe: Option<PathBuf>,
+    loudness: Option<LoudnessTarget>,
+    file_name: String,
+    tag_output: bool,
+    verify_retries: Option<u32>,
+
+    on_progress: Option<ProgressCallback>,
+    on_state_change: Option<StateChangeCallback>,
+}
+
+impl ClipRequest{
+    pub fn new(url: impl Into<String>, output: impl Into<PathBuf>) -> Self{
+        Self{
+            url: url.into(),
+            output: output.into(),
+            segment: None,
+            format: FormatSelector::default(),
+            profile: EncodeProfile::default(),
+            cut_mode: CutMode::default(),
+            reframe: None,
+            layout: None,
+            captions: None,
+            burn_captions: None,
+            export_captions: None,
+            #[cfg(feature = "whisper")]
+            transcribe: None,
+            loudness: None,
+            file_name: DEFAULT_FILE_NAME.to_string(),
+            tag_output: true,
+            verify_retries: None,
+
+            on_progress: None,
+            on_state_change: None,
+        }
+    }
+
+    ///
+    /// Cuts from `start_ms` to `end_ms` instead of the range the clip page reports. Needed for
+    /// urls that aren't clips, like `watch?v=`, `youtu.be/` or `/live/` links.
+    ///
+    pub fn time(self, start_ms: u64, end_ms: u64) -> Self{
+        self.segment(Segment::Range(ClipTime(start_ms, end_ms)))
+    }
+
+    ///
+    /// Cuts `duration_ms` starting at the url's `?t=` timestamp (or the clip's start)
+    ///
+    pub fn duration(self, duration_ms: u64) -> Self{
+        self.segment(Segment::Duration(duration_ms))
+    }
+
+    pub fn segment(mut self, segment: Segment) -> Self{
+        self.segment = Some(segment);
+        self
+    }
+
+    ///
+    /// Chooses which of the clip's formats are downloaded, best ≤1080p preferring avc1 by default
+    ///
+    pub fn format(mut self, selector: FormatSelector) -> Self{
+        self.format = selector;
+        self
+    }
+
+    ///
+    /// How the clip is encoded, [`EncodeProfile::default`] (H.264/AAC in mp4) unless set. The
+    /// output path's extension has to match the profile's container, or be left off.
+    ///
+    pub fn profile(mut self, profile: EncodeProfile) -> Self{
+        self.profile = profile;
+        self
+    }
+
+    ///
+    /// Whether the clip is re-encoded, copied or smart cut, see [`CutMode`]
+    ///
+    pub fn cut_mode(mut self, cut_mode: CutMode) -> Self{
+        self.cut_mode = cut_mode;
+        self
+    }
+
+    ///
+    /// Crops or pads the clip to a vertical, square or portrait frame for Shorts and the like
+    ///
+    pub fn reframe(mut self, reframe: Reframe) -> Self{
+        self.reframe = Some(reframe);
+        self
+    }
+
+    ///
+    /// Composes the clip from regions of the source frame, overriding the layout saved for the
+    /// clip's channel (see [`Clypper::layouts`])
+    ///
+    pub fn layout(mut self, layout: LayoutTemplate) -> Self{
+        self.layout = Some(layout);
+        self
+    }
+
+    ///
+    /// Fetches the source video's `language` captions for [`ClipRequest::burn_captions`] and
+    /// [`ClipRequest::export_captions`], preferring a track made by a person. Falls back on an
+    /// auto-generated one if `auto_generated` is set.
+    ///
+    pub fn captions(mut self, language: impl Into<String>, auto_generated: bool) -> Self{
+        self.captions = Some((language.into(), auto_generated));
+        self
+    }
+
+    ///
+    /// Burns the captions into the clip in `style`. Needs [`ClipRequest::captions`] and a re-encode.
+    ///
+    pub fn burn_captions(mut self, style: CaptionStyle) -> Self{
+        self.burn_captions = Some(style);
+        self
+    }
+
+    ///
+    /// Writes the captions next to the output in `format`, with the output's name. Needs [`ClipRequest::captions`].
+    ///
+    pub fn export_captions(mut self, format: SubtitleFormat) -> Self{
+        self.export_captions = Some(format);
+        self
+    }
+
+    ///
+    /// Makes the captions by running the whisper model at `model` over the clip's audio instead
+    /// of fetching the site's. The language set with [`ClipRequest::captions`] is used if there
+    /// is one, otherwise it's detected.
+    ///
+    #[cfg(feature = "whisper")]
+    pub fn transcribe(mut self, model: impl Into<PathBuf>) -> Self{
+        self.transcribe = Some(model.into());
+        self
+    }
+
+    ///
+    /// Evens out the clip's loudness to `target`, e.g. [`LoudnessTarget::STREAMING`] so it doesn't
+    /// stand out next to other streams' clips. Can't be done with [`CutMode::StreamCopy`].
+    ///
+    pub fn normalize_loudness(mut self, target: LoudnessTarget) -> Self{
+        self.loudness = Some(target);
+        self
+    }
+
+    ///
+    /// The name given to the file when the output path is a directory, filled in from the clip's
+    /// metadata (see [`ClipMetadata::file_name`]). Defaults to `{channel} - {title}`.
+    ///
+    pub fn file_name(mut self, template: impl Into<String>) -> Self{
+        self.file_name = template.into();
+        self
+    }
+
+    ///
+    /// Whether the clip's title, channel, date and url are written into the output's tags, on by default
+    ///
+    pub fn tag_output(mut self, tag_output: bool) -> Self{
+        self.tag_output = tag_output;
+        self
+    }
+
+    ///
+    /// Checks the finished file with ffprobe for the profile's codecs and the clip's length,
+    /// encoding again up to `retries` times if it doesn't match
+    ///
+    pub fn verify(mut self, retries: u32) -> Self{
+        self.verify_retries = Some(retries);
+        self
+    }
+
+    pub fn progress_callback(mut self, callback: impl Fn(ProgressReport) + Send + 'static) -> Self{
+        self.on_progress = Some(Box::new(callback));
+        self
+    }
+
+    pub fn state_change_callback(mut self, callback: impl Fn(FFmpegState) + Send + 'static) -> Self{
+        self.on_state_change = Some(Box::new(callback));
+        self
+    }
+
+    pub fn url(&self) -> &str{
+        self.url.as_str()
+    }
+
+    pub fn output_path(&self) -> &PathBuf{
+        &self.output
+    }
+}
+
+///
+/// Handle to a clip download running in the background, returned by [`Clypper::submit`].
+///
+pub struct ClipJob{
+    control: FFmpegControl,
+    metadata: Arc<Mutex<Option<ClipMetadata>>>,
+    handle: JoinHandle<Result<FFmpegOutput, ClypperError>>,
+}
+
+impl ClipJob{
+    ///
+    /// The last state reported by ffmpeg for this job
+    ///
+    pub fn state(&self) -> FFmpegState{
+        self.control.status()
+    }
+
+    ///
+    /// The clip's metadata, once it has been extracted
+    ///
+    pub fn metadata(&self) -> Option<ClipMetadata>{
+        self.metadata.lock().unwrap().clone()
+    }
+
+    pub fn is_finished(&self) -> bool{
+        self.handle.is_finished()
+    }
+
+    ///
+    /// Stops the job and deletes the partial output. A job still being extracted stops before ffmpeg starts.
+    ///
+    pub fn cancel(&self) -> Result<(), ClypperError>{
+        self.control.cancel()
+    }
+
+    pub fn pause(&self) -> Result<(), ClypperError>{
+        self.control.pause()
+    }
+
+    pub fn resume(&self) -> Result<(), ClypperError>{
+        self.control.resume()
+    }
+
+    ///
+    /// A cloneable handle for controlling the job from another thread
+    ///
+    pub fn control(&self) -> FFmpegControl{
+        self.control.clone()
+    }
+
+    ///
+    /// Blocks until the clip has been extracted and encoded
+    ///
+    pub fn wait(self) -> Result<FFmpegOutput, ClypperError>{
+        match self.handle.join(){
+            Ok(result) => result,
+            Err(_) => Err(ClypperError::FFmpeg("clip job panicked".to_string())),
+        }
+    }
+}
+
+///
+/// The entry point for tools built on top of clypper. Wraps extraction and encoding so a clip
+/// can be downloaded from nothing but its url and an output path.
+///
+/// Unless given an [`Clypper::installation`], ffmpeg is found and probed once, by the first job
+/// whose clip extracts, so jobs it can't handle fail before it starts. If it can't be found the
+/// jobs run `ffmpeg` from `PATH` unchecked.
+///
+/// ```no_run
+/// use clypperlib::{Clypper, ClipRequest};
+///
+/// let clypper = Clypper::new().unwrap();
+/// let job = clypper.submit(ClipRequest::new("https://www.youtube.com/clip/...", "clip.mp4"));
+/// job.wait().unwrap();
+/// ```
+///
+pub struct Clypper{
+    registry: Arc<ExtractorRegistry>,
+    installation: Option<Arc<FFmpegInstallation>>,
+    ///What the first job found when no installation was given
+    discovered: Arc<OnceLock<Option<Arc<FFmpegInstallation>>>>,
+    layouts: Option<Arc<LayoutStore>>,
+}
+
+impl Clypper{
+    pub fn new() -> Result<Self, ClypperError>{
+        Ok(Self::with_registry(ExtractorRegistry::with_defaults()?))
+    }
+
+    ///
+    /// Fetches clip pages through `client` instead of curl, e.g. a [`crate::extract::http::FixtureClient`]
+    ///
+    pub fn with_client(client: impl HttpClient + 'static) -> Result<Self, ClypperError>{
+        Ok(Self::with_registry(ExtractorRegistry::with_client(client)?))
+    }
+
+    ///
+    /// Uses `registry` to extract clips, for tools that add their own [`crate::extract::extractor::SiteExtractor`]s
+    ///
+    pub fn with_registry(registry: ExtractorRegistry) -> Self{
+        Self{
+            registry: Arc::new(registry),
+            installation: None,
+            discovered: Arc::new(OnceLock::new()),
+            layouts: None,
+        }
+    }
+
+    ///
+    /// Encodes with `installation` instead of the ffmpeg found on `PATH`. Jobs it can't handle fail before ffmpeg starts.
+    ///
+    pub fn installation(mut self, installation: FFmpegInstallation) -> Self{
+        self.installation = Some(Arc::new(installation));
+        self
+    }
+
+    ///
+    /// Lays out each clip with the template saved for its channel, unless the request is
+    /// reframed or brings its own layout
+    ///
+    pub fn layouts(mut self, layouts: LayoutStore) -> Self{
+        self.layouts = Some(Arc::new(layouts));
+        self
+    }
+
+    pub fn registry(&self) -> &ExtractorRegistry{
+        &self.registry
+    }
+
+    ///
+    /// Starts downloading the requested clip on a background thread
+    ///
+    pub fn submit(&self, request: ClipRequest) -> ClipJob{
+        let registry = self.registry.clone();
+        let layouts = self.layouts.clone();
+        let mut ffmpeg = FFmpeg::new();
+        let discovered = match self.installation{
+            Some(ref installation) => {
+                ffmpeg.installation(installation.clone());
+                None
+            },
+            None => Some(self.discovered.clone()),
+        };
+        let control = ffmpeg.control();
+        let metadata = Arc::new(Mutex::new(None));
+        let job_control = control.clone();
+        let job_metadata = metadata.clone();
+        let handle = thread::spawn(move || {
+            let result = run_request(registry, layouts, discovered, request, &mut ffmpeg, job_metadata);
+            //Failures before ffmpeg started never reached the job's state
+            if let Err(ref err) = result{
+                if !job_control.status().is_done(){
+                    job_control.set_state(match err{
+                        ClypperError::Cancelled => FFmpegState::Cancelled,
+                        _ => FFmpegState::Error,
+                    });
+                }
+            }
+            result
+        });
+        ClipJob{ control, metadata, handle }
+    }
+
+    ///
+    /// Downloads the requested clip, blocking until it is done
+    ///
+    pub fn download(&self, request: ClipRequest) -> Result<FFmpegOutput, ClypperError>{
+        self.submit(request).wait()
+    }
+}
+
+fn extract<'url>(registry: &ExtractorRegistry, url: &'url str, segment: Option<Segment>, format: &FormatSelector) -> Result<Clip<'url>, ClypperError>{
+    match segment{
+        Some(segment) => registry.extract_segment(url, segment, format),
+        None => registry.extract_with(url, format),
+    }
+}
+
+///
+/// The clip's captions from the site's track for `language`, timed from the start of the clip
+///
+fn fetch_captions(registry: &ExtractorRegistry, clip: &Clip, language: Option<(String, bool)>) -> Result<Captions, ClypperError>{
+    let (language, auto_generated) = language.ok_or_else(|| ClypperError::InvalidField("captions", "no language asked for".to_string()))?;
+    let track = find_track(&clip.captions, language.as_str(), auto_generated).ok_or(ClypperError::NoCaptions(language))?;
+    //Tracks are timed against the whole video, so trim them where the clip sits in it
+    Ok(registry.captions(clip.url, track)?.trim(clip.source_time.unwrap_or(clip.time)))
+}
+
+///
+/// The clip's captions from running `model` over its audio with the ffmpeg the job will use
+///
+#[cfg(feature = "whisper")]
+fn transcribe_clip(clip: &Clip, model: &Path, language: Option<String>, ffmpeg: &FFmpeg) -> Result<Captions, ClypperError>{
+    let mut transcriber = Transcriber::load(model)?;
+    if let Some(language) = language{
+        transcriber = transcriber.language(language);
+    }
+    transcriber.transcribe_input(ffmpeg.program().as_path(), clip.resource.1.as_str(), clip.time.0, clip.time.1)
+}
+
+fn run_request(
+    registry: Arc<ExtractorRegistry>,
+    layouts: Option<Arc<LayoutStore>>,
+    discovered: Option<Arc<OnceLock<Option<Arc<FFmpegInstallation>>>>>,
+    request: ClipRequest,
+    ffmpeg: &mut FFmpeg,
+    metadata: Arc<Mutex<Option<ClipMetadata>>>,
+) -> Result<FFmpegOutput, ClypperError>{
+    let ClipRequest{
+        url, output, segment, format, profile, cut_mode, reframe, layout, captions, burn_captions, export_captions,
+        #[cfg(feature = "whisper")]
+        transcribe,
+        loudness, file_name, tag_output, verify_retries, on_progress, on_state_change,
+    } = request;
+    let clip = extract(&registry, url.as_str(), segment, &format)?;
+    *metadata.lock().unwrap() = Some(clip.metadata.clone());
+    //Probing runs ffmpeg several times, so it waits for a clip worth encoding
+    let installation = discovered.and_then(|discovered| discovered.get_or_init(|| FFmpegInstallation::discover().ok().map(Arc::new)).clone());
+    if let Some(installation) = installation{
+        ffmpeg.installation(installation);
+    }
+    let output = if output.is_dir(){
+        output.join(format!("{}.{}", clip.metadata.file_name(file_name.as_str()), profile.container.extension()))
+    }else{
+        output
+    };
+
+    if tag_output{
+        ffmpeg.tag(&clip);
+    }
+    let layout = match (layout, &reframe, layouts){
+        (Some(layout), _, _) => Some(layout),
+        (None, None, Some(layouts)) => layouts.for_clip(&clip.metadata).cloned(),
+        _ => None,
+    };
+    if let Some(retries) = verify_retries{
+        let mut verification = Verification::new(clip.time.duration_ms());
+        if cut_mode == CutMode::StreamCopy{
+            //Copies keep the source's codecs and start on the keyframe before the clip
+            verification.tolerance_ms = STREAM_COPY_TOLERANCE_MS;
+        }else{
+            verification.video_codec = codec_name(profile.video_codec.as_str()).map(str::to_string);
+            verification.audio_codec = codec_name(profile.audio_codec.as_str()).map(str::to_string);
+        }
+        verification.resolution = layout.as_ref().map(|layout| layout.size).or(reframe.as_ref().map(Reframe::size));
+        ffmpeg.verify(verification).verify_retries(retries);
+    }
+    if burn_captions.is_some() || export_captions.is_some(){
+        #[cfg(feature = "whisper")]
+        let captions = match transcribe{
+            Some(model) => transcribe_clip(&clip, model.as_path(), captions.map(|(language, _)| language), ffmpeg)?,
+            None => fetch_captions(&registry, &clip, captions)?,
+        };
+        #[cfg(not(feature = "whisper"))]
+        let captions = fetch_captions(&registry, &clip, captions)?;
+        if let Some(format) = export_captions{
+            let play_res = layout.as_ref().map(|layout| layout.size).or(reframe.as_ref().map(Reframe::size)).unwrap_or(DEFAULT_PLAY_RES);
+            let style = burn_captions.clone().unwrap_or_default();
+            fs::write(output.with_extension(format.extension()), format.render(&captions, &style, play_res))?;
+        }
+        if let Some(style) = burn_captions{
+            ffmpeg.burn_captions(captions, style);
+        }
+    }
+    ffmpeg.profile(profile).cut_mode(cut_mode);
+    if let Some(target) = loudness{
+        ffmpeg.normalize_loudness(target);
+    }
+    match (layout, reframe){
+        (Some(layout), _) => ffmpeg.layout(layout),
+        (None, Some(reframe)) => ffmpeg.reframe(reframe),
+        (None, None) => ffmpeg,
+    };
+    if let Some(callback) = on_state_change{
+        ffmpeg.state_change_callback(callback);
+    }
+    if let Some(callback) = on_progress{
+        ffmpeg.progress_callback(callback);
+    }
+    let refresh_url = url.clone();
+    ffmpeg.clip(&clip)?
+        .output(output.to_string_lossy())?
+        //Jobs can sit in a queue for longer than youtube's stream urls live
+        .refresh_inputs(move ||{
+            let ClipResource(video, audio) = extract(&registry, refresh_url.as_str(), segment, &format)?.resource;
+            Ok(vec![video, audio])
+        })
+        .spawn()?
+        .wait()
+}
@@ -0,0 +1,107 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::ClypperError;
+
+use super::verify::{run_ffprobe, ProbeStream};
+
+///
+/// How the clip's time range is cut out of its source streams
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CutMode{
+    ///Decode and encode the whole clip. Slow, but exact and the only way to change codec.
+    #[default]
+    Reencode,
+    ///Copy the streams as they are. Fast and lossless, but the clip starts on the keyframe before
+    ///the requested start, up to a few seconds early.
+    StreamCopy,
+    ///Encode only the partial GOPs before the first and after the last keyframe in the clip and
+    ///copy everything between them. Exact and mostly lossless, but needs the profile to encode to
+    ///the source's codec and pixel format.
+    SmartCut,
+    ///[`CutMode::SmartCut`] when the source allows it, [`CutMode::Reencode`] otherwise
+    Auto,
+}
+
+///
+/// The keyframes a smart cut copies between, in microseconds of source time
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CutPoints{
+    pub first_keyframe_us: u64,
+    pub last_keyframe_us: u64,
+}
+
+impl CutPoints{
+    ///
+    /// Where to split a cut from `start_ms` to `end_ms` given the source's keyframe times.
+    /// `None` if fewer than two keyframes fall inside it, there's nothing to copy then.
+    ///
+    pub fn find(keyframes_us: &[u64], start_ms: u64, end_ms: u64) -> Option<Self>{
+        let (start_us, end_us) = (start_ms * 1000, end_ms * 1000);
+        let first_keyframe_us = keyframes_us.iter().copied().filter(|keyframe| *keyframe >= start_us).min()?;
+        let last_keyframe_us = keyframes_us.iter().copied().filter(|keyframe| *keyframe <= end_us).max()?;
+        (first_keyframe_us < last_keyframe_us).then_some(Self{ first_keyframe_us, last_keyframe_us })
+    }
+}
+
+///
+/// Reads the output of `ffprobe -show_entries frame=pts_time -of csv=p=0`, one time in seconds
+/// per line. Frames without a timestamp print `N/A` and are skipped.
+///
+pub fn parse_keyframes(output: &str) -> Vec<u64>{
+    let mut keyframes: Vec<u64> = output.lines()
+        .filter_map(|line| line.trim().trim_end_matches(',').parse::<f64>().ok())
+        .filter(|seconds| *seconds >= 0.0)
+        .map(|seconds| (seconds * 1_000_000.0).round() as u64)
+        .collect();
+    keyframes.sort_unstable();
+    keyframes.dedup();
+    keyframes
+}
+
+///
+/// The keyframe times of `input`'s video between `start_ms` and `end_ms`, in microseconds
+///
+pub fn probe_keyframes(ffprobe: &Path, input: &str, start_ms: u64, end_ms: u64) -> Result<Vec<u64>, ClypperError>{
+    let interval = format!("{:.3}%{:.3}", start_ms as f64 / 1000.0, end_ms as f64 / 1000.0);
+    let output = run_ffprobe(ffprobe, &[
+        "-select_streams", "v:0",
+        "-skip_frame", "nokey",
+        "-show_entries", "frame=pts_time",
+        "-of", "csv=p=0",
+        "-read_intervals", interval.as_str(),
+    ], input)?;
+    if !output.status.success(){
+        return Err(ClypperError::FFmpeg(format!("ffprobe can't read keyframes: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(parse_keyframes(String::from_utf8_lossy(&output.stdout).as_ref()))
+}
+
+///
+/// The first stream of `input` matching `selector`, e.g. `v:0` or `a:0`
+///
+pub fn probe_stream(ffprobe: &Path, input: &str, selector: &str) -> Result<ProbeStream, ClypperError>{
+    #[derive(Deserialize)]
+    struct Streams{
+        #[serde(default)]
+        streams: Vec<ProbeStream>,
+    }
+
+    let output = run_ffprobe(ffprobe, &[
+        "-select_streams", selector,
+        "-show_entries", "stream=codec_type,codec_name,width,height,pix_fmt",
+        "-of", "json",
+    ], input)?;
+    if !output.status.success(){
+        return Err(ClypperError::FFmpeg(format!("ffprobe can't read {}: {}", input, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    let streams: Streams = serde_json::from_slice(&output.stdout)
+        .map_err(|err| ClypperError::InvalidField("ffprobe", err.to_string()))?;
+    streams.streams.into_iter()
+        .next()
+        .ok_or_else(|| ClypperError::FFmpeg(format!("{} has no {} stream", input, selector)))
+}
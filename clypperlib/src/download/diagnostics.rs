@@ -0,0 +1,133 @@
+use std::{collections::VecDeque, process::ExitStatus};
+
+use crate::error::{ClypperError, FFmpegFailure};
+
+///How many of ffmpeg's last stderr lines are kept for error reports
+const TAIL_LINES: usize = 32;
+
+///
+/// Collects what ffmpeg says on stderr while it runs, keeping the last few lines and the first
+/// line that explains why it failed. Progress lines should be filtered out before they get here.
+///
+#[derive(Clone, Debug)]
+pub struct Diagnostics{
+    tail: VecDeque<String>,
+    failure: Option<FFmpegFailure>,
+    inputs: Vec<String>,
+    output: String,
+}
+
+impl Diagnostics{
+    ///
+    /// `inputs` and `output` are the urls and path ffmpeg was given, so errors can be pinned on one of them
+    ///
+    pub fn new(inputs: Vec<String>, output: impl Into<String>) -> Self{
+        Self{
+            tail: VecDeque::with_capacity(TAIL_LINES),
+            failure: None,
+            inputs,
+            output: output.into(),
+        }
+    }
+
+    pub fn push(&mut self, line: impl Into<String>){
+        let line = line.into();
+        if line.trim().is_empty(){
+            return;
+        }
+        //The first error is the cause, whatever follows is usually fallout from it. Only some
+        //lines say which input an HTTP error is about, so a later one can still name it.
+        match (&self.failure, self.classify(line.as_str())){
+            (None, failure) => self.failure = failure,
+            (Some(unnamed), Some(named)) if std::mem::discriminant(unnamed) == std::mem::discriminant(&named)
+                && failed_input(unnamed) == Some(None)
+                && failed_input(&named).is_some_and(|url| url.is_some()) => self.failure = Some(named),
+            _ => {},
+        }
+        if self.tail.len() == TAIL_LINES{
+            self.tail.pop_front();
+        }
+        self.tail.push_back(line);
+    }
+
+    pub fn failure(&self) -> Option<&FFmpegFailure>{
+        self.failure.as_ref()
+    }
+
+    pub fn tail(&self) -> impl Iterator<Item = &str>{
+        self.tail.iter().map(String::as_str)
+    }
+
+    ///
+    /// The input refused with 403, worth retrying with fresh urls. Every input, comma separated,
+    /// when ffmpeg didn't say which.
+    ///
+    pub fn forbidden_input(&self) -> Option<String>{
+        match self.failure{
+            Some(FFmpegFailure::InputForbidden(Some(ref url))) => Some(url.clone()),
+            Some(FFmpegFailure::InputForbidden(None)) => Some(self.inputs.join(", ")),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The error for ffmpeg exiting with `status`
+    ///
+    pub fn into_error(self, status: ExitStatus) -> ClypperError{
+        ClypperError::FFmpegExit{
+            status: status.code(),
+            failure: self.failure.unwrap_or_default(),
+            stderr_tail: self.tail.into(),
+        }
+    }
+
+    fn classify(&self, line: &str) -> Option<FFmpegFailure>{
+        if line.contains("403 Forbidden") || line.contains("HTTP error 403"){
+            return Some(FFmpegFailure::InputForbidden(self.input_for(line)));
+        }
+        if line.contains("404 Not Found") || line.contains("HTTP error 404"){
+            return Some(FFmpegFailure::InputNotFound(self.input_for(line)));
+        }
+        if let Some(rest) = line.split_once("Unknown encoder").map(|(_, rest)| rest){
+            return Some(FFmpegFailure::UnknownEncoder(rest.trim().trim_matches('\'').to_string()));
+        }
+        if line.contains("No space left on device"){
+            return Some(FFmpegFailure::DiskFull);
+        }
+        //Older ffmpegs start the line with the path, 6.1 and later prefix it with the muxer, e.g.
+        //`[out#0/mp4 @ 0x...] Error opening output /clips/out.mp4: No such file or directory`
+        let about_output = !self.output.is_empty() && line.contains(self.output.as_str());
+        if line.contains("Unable to find a suitable output format")
+            || line.contains("Error opening output")
+            || (about_output && ["No such file or directory", "Permission denied", "Invalid argument", "Is a directory"].iter().any(|error| line.contains(error)))
+        {
+            return Some(FFmpegFailure::InvalidOutput(line.to_string()));
+        }
+        None
+    }
+
+    ///
+    /// ffmpeg prefixes HTTP errors with the url, but only for some protocols. `None` when the line
+    /// doesn't say and there's more than one input it could be.
+    ///
+    fn input_for(&self, line: &str) -> Option<String>{
+        let only = match self.inputs.as_slice(){
+            [input] => Some(input),
+            _ => None,
+        };
+        self.inputs.iter()
+            .find(|input| line.starts_with(input.as_str()))
+            .or(only)
+            .cloned()
+    }
+}
+
+///
+/// The url an HTTP failure is about
+///
+fn failed_input(failure: &FFmpegFailure) -> Option<Option<&str>>{
+    match failure{
+        FFmpegFailure::InputForbidden(url) | FFmpegFailure::InputNotFound(url) => Some(url.as_deref()),
+        _ => None,
+    }
+}
@@ -1,96 +1,124 @@
-use std::{
-    io::{BufRead, BufReader},
-    process::{self, Command, Stdio},
-    thread,
-};
+use std::sync::Arc;
 
 use indicatif::{ProgressBar, ProgressStyle};
-use regex::Regex;
 
-use crate::extract::extractor::{Clip, ClypperError};
-use numtoa::NumToA;
+use crate::{error::ClypperError, extract::extractor::{Clip, ClipResource}};
 
-use chrono::{naive::NaiveTime, NaiveDateTime};
+use super::{ffmpeg::{FFmpeg, FFmpegControl, FFmpegOutput, FFmpegState}, installation::FFmpegInstallation, profile::EncodeProfile};
 
-type FFmpegHandle = process::Child;
+type RefreshCallback = Box<dyn FnMut() -> Result<ClipResource, ClypperError> + Send>;
 
+///
+/// Downloads a clip with [`FFmpeg`], showing its progress as a bar in the terminal. Anything the
+/// builder can do, e.g. reframing or normalizing loudness, is set up on [`Downloader::ffmpeg`].
+///
+/// ```no_run
+/// use clypperlib::{Downloader, ExtractorRegistry, LoudnessTarget, ReframeMode, Reframe, AspectRatio};
+///
+/// let registry = ExtractorRegistry::with_defaults().unwrap();
+/// let clip = registry.extract("https://www.youtube.com/clip/...").unwrap();
+/// let mut downloader = Downloader::new(clip, "clip.mp4".to_string());
+/// downloader.ffmpeg()
+///     .reframe(Reframe::new(AspectRatio::Vertical, ReframeMode::Center))
+///     .normalize_loudness(LoudnessTarget::STREAMING);
+/// downloader.download().unwrap();
+/// ```
+///
 pub struct Downloader<'dl> {
     clip: Clip<'dl>,
     out: String,
-
-    ffmpeg_handle: Option<FFmpegHandle>,
+    refresh: Option<RefreshCallback>,
+    ffmpeg: FFmpeg,
 
     progress_bar: ProgressBar,
 }
 
 impl<'dl> Downloader<'dl> {
     pub fn new(clip: Clip<'dl>, out: String) -> Self {
-        let pb = ProgressBar::new(clip.time.1);
+        Self::with_ffmpeg(clip, out, FFmpeg::new())
+    }
+
+    ///
+    /// Downloads with an already configured `ffmpeg`. Its inputs, output and callbacks are
+    /// replaced by the clip's and the downloader's.
+    ///
+    pub fn with_ffmpeg(clip: Clip<'dl>, out: String, ffmpeg: FFmpeg) -> Self {
+        let pb = ProgressBar::new(clip.time.duration_ms());
         pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.white/grey} {percent}% {msg}").unwrap());
         Self {
-            clip: clip.clone(),
+            clip,
             out,
-            ffmpeg_handle: None,
+            refresh: None,
+            ffmpeg,
             progress_bar: pb,
         }
     }
 
-    pub fn download(&mut self) -> Result<(), ClypperError> {
-        let mut buffer = [0u8; 20];
-        let start = format!("{}ms", self.clip.time.0.numtoa_str(10, &mut buffer));
-        let mut buffer2 = [0u8; 20];
-        let end = format!("{}ms", self.clip.time.1.numtoa_str(10, &mut buffer2));
+    ///
+    /// Fetches fresh stream urls for the clip when the current ones have expired or are refused with 403
+    ///
+    pub fn refresh(mut self, refresh: impl FnMut() -> Result<ClipResource, ClypperError> + Send + 'static) -> Self{
+        self.refresh = Some(Box::new(refresh));
+        self
+    }
+
+    ///
+    /// Encodes with this ffmpeg instead of the one found on `PATH`
+    ///
+    pub fn installation(mut self, installation: Arc<FFmpegInstallation>) -> Self{
+        self.ffmpeg.installation(installation);
+        self
+    }
+
+    ///
+    /// Encodes with `profile` instead of the one picked from the output's extension
+    ///
+    pub fn profile(mut self, profile: EncodeProfile) -> Self{
+        self.ffmpeg.profile(profile);
+        self
+    }
+
+    ///
+    /// The builder the clip will be encoded with, for the options the downloader doesn't wrap
+    ///
+    pub fn ffmpeg(&mut self) -> &mut FFmpeg{
+        &mut self.ffmpeg
+    }
 
-        let mut command = Command::new("ffmpeg");
-        command
-            .args(["-ss", start.as_str()])
-            .args(["-to", end.as_str()])
-            .args(["-i", self.clip.resource.0.as_str()])
-            .args(["-ss", start.as_str()])
-            .args(["-to", end.as_str()])
-            .args(["-i", self.clip.resource.1.as_str()])
-            .args(["-map", "0:v"])
-            .args(["-map", "1:a"])
-            .args(["-c:v", "libx264"])
-            .args(["-c:a", "aac"])
-            .arg("pipe:1")
-            .arg("-y")
-            .arg(self.out.as_str());
-        command.stderr(Stdio::piped()).stdout(Stdio::piped());
-        let handle = match command.spawn() {
-            Ok(child) => child,
-            Err(_) => return Err(ClypperError::FFmpegError),
-        };
+    ///
+    /// Cancels or pauses the download from another thread once it's started
+    ///
+    pub fn control(&self) -> FFmpegControl{
+        self.ffmpeg.control()
+    }
 
-        let end_time = NaiveDateTime::from_timestamp_millis(self.clip.time.1 as i64).unwrap().time();
-        let out = handle.stdout.unwrap();
+    ///
+    /// Downloads the clip, blocking until ffmpeg is done. A handle from [`Downloader::control`]
+    /// taken before controls this download.
+    ///
+    pub fn download(mut self) -> Result<FFmpegOutput, ClypperError> {
         let pb = self.progress_bar.clone();
-        pb.set_message("Loading ffmpeg...");
-        pb.inc(0);
-        //let stdout_thread = thread::spawn(move ||{
-            let time_re = Regex::new(r#"time=(\d+:\d+:\d+\.\d+)"#).unwrap();
-            let buf = BufReader::new(out);
-            for line in buf.lines(){
-                let line = line.unwrap();
-                let ln_str = line.as_str();
-                let capture = if let Some(capture) = time_re.captures(ln_str){
-                    capture
-                }else{
-                    continue
-                };
-                pb.set_message("Downloading clip...");
-                let time = capture.get(1).unwrap().as_str();
-                let naive_time = NaiveTime::parse_from_str(time, "%H:%M:%S.%f").unwrap();
-                let signed_delta = NaiveTime::signed_duration_since(naive_time, end_time).num_milliseconds();
-                let delta: u64 = if signed_delta < 0{
-                    (signed_delta * -1) as u64
-                } else {
-                    signed_delta as u64
-                };
-                pb.inc(delta);
-            }
-        //});
-        //stdout_thread.join().expect("Failed to join stdout thread");
-        Ok(())
+        let pb_state = self.progress_bar.clone();
+        let ffmpeg = &mut self.ffmpeg;
+        ffmpeg.clip(&self.clip)?
+            .tag(&self.clip)
+            .output(self.out.as_str())?
+            .state_change_callback(move |state| match state{
+                FFmpegState::Starting => pb_state.set_message("Loading ffmpeg..."),
+                FFmpegState::Downloading(_) => pb_state.set_message("Downloading clip..."),
+                FFmpegState::Paused => pb_state.set_message("Paused"),
+                FFmpegState::Finished => pb_state.finish_with_message("Done!"),
+                FFmpegState::Cancelled => pb_state.abandon_with_message("Cancelled"),
+                FFmpegState::Error => pb_state.abandon_with_message("Failed"),
+                FFmpegState::NotStarted => {},
+            })
+            .progress_callback(move |report| pb.set_position(report.out_time_ms));
+        if let Some(mut refresh) = self.refresh.take(){
+            ffmpeg.refresh_inputs(move || {
+                let ClipResource(video, audio) = refresh()?;
+                Ok(vec![video, audio])
+            });
+        }
+        ffmpeg.spawn()?.wait()
     }
 }
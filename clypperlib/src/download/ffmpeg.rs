@@ -1,17 +1,37 @@
 use std::{
-    fmt::Display,
-    io::{BufRead, BufReader, Stdout},
-    process::{self, Command, Stdio},
-    sync::{Arc, Mutex},
-    thread, ffi::OsStr, borrow::Cow,
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
 };
 
-use regex::Regex;
+use crate::{error::ClypperError, extract::{captions::Captions, extractor::{is_url_expired, Clip}}};
 
-use crate::extract::extractor::{Clip, ClipResource, ClipTime};
+use super::{
+    cut::{probe_keyframes, probe_stream, CutMode, CutPoints},
+    diagnostics::Diagnostics,
+    filter::{Filter, FilterGraph, StreamType},
+    installation::{default_ffmpeg, default_ffprobe, FFmpegInstallation},
+    layout::LayoutTemplate,
+    loudness::{LoudnessReport, LoudnessReportParser, LoudnessTarget},
+    profile::{codec_name, Container, EncodeProfile},
+    progress::{ProgressParser, ProgressReport},
+    reframe::Reframe,
+    subtitles::{to_ass, CaptionStyle, DEFAULT_PLAY_RES},
+    verify::{Verification, VerificationReport},
+};
+
+///H.264 encoders to fall back on, best first, when none was asked for
+const H264_ENCODERS: [&str; 5] = ["libx264", "libopenh264", "h264_videotoolbox", "h264_nvenc", "h264_qsv"];
+const AAC_ENCODERS: [&str; 2] = ["aac", "libfdk_aac"];
+///The graph output normalised audio is mapped from
+const LOUDNORM_LABEL: &str = "loudnorm";
 
-type FFmpegHandle = process::Child;
-type FFmpegThread = std::thread::JoinHandle<Result<(), FFmpegError>>;
+type ProgressCallback = Box<dyn Fn(ProgressReport) + Send>;
+type StateChangeCallback = Box<dyn Fn(FFmpegState) + Send>;
+type RefreshCallback = Box<dyn FnMut() -> Result<Vec<String>, ClypperError> + Send>;
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum FFmpegState {
@@ -19,211 +39,1127 @@ pub enum FFmpegState {
     NotStarted,
     Starting,
     Downloading(u64),
+    Paused,
     Finished,
+    Cancelled,
     Error,
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct FFmpegError {
-    pub message: String,
-    pub state: FFmpegState,
+impl FFmpegState{
+    ///
+    /// Whether the job is over, one way or another
+    ///
+    pub fn is_done(&self) -> bool{
+        matches!(self, Self::Finished | Self::Cancelled | Self::Error)
+    }
 }
 
-#[derive(Debug, Default)]
-pub struct FFmpegInput<'input> {
-    url: &'input str,
-    start_ms: &'input str,
-    end_ms: &'input str,
+///
+/// Builds an ffmpeg command line and runs it in the background. [`FFmpeg::spawn`] returns right
+/// away with an [`FFmpegJob`] to wait on or control the encode.
+///
+#[derive(Default)]
+pub struct FFmpeg{
+    inputs: Vec<String>,
+    start_ms: u64,
+    end_ms: u64,
+    output: Option<String>,
+    metadata: Vec<(String, String)>,
+    refresh: Option<RefreshCallback>,
+    control: FFmpegControl,
+    installation: Option<Arc<FFmpegInstallation>>,
+    profile: Option<EncodeProfile>,
+    cut_mode: CutMode,
+    reframe: Option<Reframe>,
+    layout: Option<LayoutTemplate>,
+    filter_graph: FilterGraph,
+    captions: Option<(Captions, CaptionStyle)>,
+    loudness: Option<LoudnessTarget>,
+    video_codec: Option<String>,
+    audio_codec: Option<String>,
+    verification: Option<Verification>,
+    verify_retries: u32,
+
+    on_progress_callback: Option<ProgressCallback>,
 }
 
-impl<'input> FFmpegInput<'input> {
-    pub fn new(url: &'input str, start_ms: &'input str, end_ms: &'input str) -> Self {
-        Self {
-            url,
+impl FFmpeg{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    ///
+    /// Called from the job's thread every time the job changes state
+    ///
+    pub fn state_change_callback(&mut self, callback: impl Fn(FFmpegState) + Send + 'static) -> &mut Self{
+        *self.control.lock_callback() = Some(Box::new(callback));
+        self
+    }
+
+    ///
+    /// Called from the job's thread every time ffmpeg reports its progress
+    ///
+    pub fn progress_callback(&mut self, callback: impl Fn(ProgressReport) + Send + 'static) -> &mut Self{
+        self.on_progress_callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn output(&mut self, output: impl Into<String>) -> Result<&mut Self, ClypperError>{
+        self.output = Some(output.into());
+        Ok(self)
+    }
+
+    pub fn time(&mut self, start_ms: u64, end_ms: u64) -> Result<&mut Self, ClypperError>{
+        self.start_ms = start_ms;
+        self.end_ms = end_ms;
+        Ok(self)
+    }
+
+    pub fn input(&mut self, url: impl Into<String>) -> Result<&mut Self, ClypperError>{
+        self.inputs.push(url.into());
+        Ok(self)
+    }
+
+    ///
+    /// Runs this ffmpeg instead of the one in [`super::installation::FFMPEG_ENV`] or on `PATH`, and
+    /// checks the job against what it supports before starting
+    ///
+    pub fn installation(&mut self, installation: Arc<FFmpegInstallation>) -> &mut Self{
+        self.installation = Some(installation);
+        self
+    }
+
+    ///
+    /// How to encode the clip. Without one the profile is picked from the output's extension:
+    /// [`EncodeProfile::default`] for mp4, mkv and mov, the `web` profile for webm.
+    ///
+    pub fn profile(&mut self, profile: EncodeProfile) -> &mut Self{
+        self.profile = Some(profile);
+        self
+    }
+
+    ///
+    /// How the time range is cut out of the inputs, [`CutMode::Reencode`] by default
+    ///
+    pub fn cut_mode(&mut self, cut_mode: CutMode) -> &mut Self{
+        self.cut_mode = cut_mode;
+        self
+    }
+
+    ///
+    /// Crops or pads the video to a vertical, square or portrait frame. Needs the clip to be
+    /// re-encoded, so it can't be combined with [`CutMode::StreamCopy`] or [`CutMode::SmartCut`].
+    ///
+    pub fn reframe(&mut self, reframe: Reframe) -> &mut Self{
+        self.reframe = Some(reframe);
+        self
+    }
+
+    ///
+    /// Composes the output from regions of the source frame, e.g. a facecam stacked over
+    /// gameplay. Like reframing it needs the clip re-encoded, and the two can't be combined.
+    ///
+    pub fn layout(&mut self, layout: LayoutTemplate) -> &mut Self{
+        self.layout = Some(layout);
+        self
+    }
+
+    ///
+    /// Runs the clip through `graph`, after the reframe or layout if there is one. The graph reads
+    /// the video from `0:v` and the audio from `1:a`, or the reframed video from `v`. Its unused
+    /// outputs, at most one video and one audio, are what gets encoded.
+    ///
+    pub fn filter_graph(&mut self, graph: FilterGraph) -> &mut Self{
+        self.filter_graph.append(graph);
+        self
+    }
+
+    ///
+    /// Burns `captions` into the video in `style`, on top of everything else. Their times count
+    /// from the start of the clip, see [`Captions::trim`].
+    ///
+    pub fn burn_captions(&mut self, captions: Captions, style: CaptionStyle) -> &mut Self{
+        self.captions = Some((captions, style));
+        self
+    }
+
+    ///
+    /// Evens out the clip's loudness to `target` in two passes: one measuring the audio, then
+    /// the encode applying a single gain worked out from it. Both are reported in
+    /// [`FFmpegOutput::loudness`]. The audio can't also go through a [`FFmpeg::filter_graph`].
+    ///
+    pub fn normalize_loudness(&mut self, target: LoudnessTarget) -> &mut Self{
+        self.loudness = Some(target);
+        self
+    }
+
+    ///
+    /// The video encoder, e.g. `libx264`, overriding the profile's. Without one the profile's
+    /// encoder is used, falling back to other encoders for the same codec if ffmpeg lacks it.
+    ///
+    pub fn video_codec(&mut self, codec: impl Into<String>) -> &mut Self{
+        self.video_codec = Some(codec.into());
+        self
+    }
+
+    ///
+    /// The audio encoder, overriding the profile's
+    ///
+    pub fn audio_codec(&mut self, codec: impl Into<String>) -> &mut Self{
+        self.audio_codec = Some(codec.into());
+        self
+    }
+
+    ///
+    /// Cuts `clip`'s time range out of its video and audio streams, replacing any inputs already set
+    ///
+    pub fn clip(&mut self, clip: &Clip) -> Result<&mut Self, ClypperError>{
+        self.inputs.clear();
+        self.time(clip.time.0, clip.time.1)?
+            .input(clip.resource.0.as_str())?
+            .input(clip.resource.1.as_str())
+    }
+
+    ///
+    /// Writes `clip`'s title, channel, date and url into the output's tags
+    ///
+    pub fn tag(&mut self, clip: &Clip) -> &mut Self{
+        let metadata = &clip.metadata;
+        let tags = [
+            ("title", metadata.display_title()),
+            ("artist", metadata.channel_name.as_deref()),
+            ("date", metadata.date.as_deref()),
+            ("comment", Some(clip.url)),
+        ];
+        for (key, value) in tags{
+            if let Some(value) = value{
+                self.metadata(key, value);
+            }
+        }
+        self
+    }
+
+    ///
+    /// Tags the output with `key=value`, e.g. `title` or `artist`
+    ///
+    pub fn metadata(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self{
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    ///
+    /// Checks the output with ffprobe once ffmpeg is done, failing with
+    /// [`ClypperError::VerificationFailed`] if it doesn't match. The report ends up in [`FFmpegOutput`].
+    ///
+    pub fn verify(&mut self, verification: Verification) -> &mut Self{
+        self.verification = Some(verification);
+        self
+    }
+
+    ///
+    /// How many times to encode again when the output fails verification, 0 by default
+    ///
+    pub fn verify_retries(&mut self, retries: u32) -> &mut Self{
+        self.verify_retries = retries;
+        self
+    }
+
+    ///
+    /// Fetches fresh input urls, in the same order as [`FFmpeg::input`] was called, when the
+    /// current ones have expired or ffmpeg gets a 403 reading them. Usually re-runs extraction.
+    ///
+    pub fn refresh_inputs(&mut self, refresh: impl FnMut() -> Result<Vec<String>, ClypperError> + Send + 'static) -> &mut Self{
+        self.refresh = Some(Box::new(refresh));
+        self
+    }
+
+    ///
+    /// A handle to the job this builder will spawn. It can be used before [`FFmpeg::spawn`], a
+    /// job cancelled that early never starts ffmpeg at all.
+    ///
+    pub fn control(&self) -> FFmpegControl{
+        self.control.clone()
+    }
+
+    ///
+    /// The ffmpeg this job will run
+    ///
+    pub fn program(&self) -> PathBuf{
+        self.installation.as_ref().map_or_else(default_ffmpeg, |installation| installation.ffmpeg.clone())
+    }
+
+    ///
+    /// Picks the program and encoders to run. With an installation, fails early if it lacks the
+    /// encoders or protocols the job needs, falling back to other H.264/AAC encoders when none
+    /// were asked for.
+    ///
+    fn check_installation(&self, profile: &EncodeProfile, graph: Option<&FilterGraph>) -> Result<(PathBuf, String, String), ClypperError>{
+        let Some(ref installation) = self.installation else{
+            let video_codec = self.video_codec.as_deref().unwrap_or(profile.video_codec.as_str());
+            let audio_codec = self.audio_codec.as_deref().unwrap_or(profile.audio_codec.as_str());
+            return Ok((default_ffmpeg(), video_codec.to_string(), audio_codec.to_string()));
+        };
+        let probes = self.verification.is_some() || matches!(self.cut_mode, CutMode::StreamCopy | CutMode::SmartCut);
+        if probes && installation.ffprobe.is_none(){
+            return Err(ClypperError::FFmpegMissing(format!("ffprobe next to {}", installation.ffmpeg.display())));
+        }
+        let pick = |codec: &Option<String>, preferred: &str| -> Result<String, ClypperError>{
+            if let Some(codec) = codec{
+                return installation.pick_encoder(&[codec.as_str()]).map(str::to_string);
+            }
+            let fallbacks = match codec_name(preferred){
+                Some("h264") => &H264_ENCODERS[..],
+                Some("aac") => &AAC_ENCODERS[..],
+                _ => &[],
+            };
+            let candidates: Vec<&str> = std::iter::once(preferred).chain(fallbacks.iter().copied()).collect();
+            installation.pick_encoder(&candidates).map(str::to_string)
+        };
+        let filters = graph.map(FilterGraph::filter_names).unwrap_or_default();
+        if let Some(filter) = filters.iter().find(|filter| !installation.has_filter(filter)){
+            return Err(ClypperError::FFmpegUnsupported(format!("this ffmpeg has no {} filter", filter)));
+        }
+        let video_codec = pick(&self.video_codec, profile.video_codec.as_str())?;
+        let audio_codec = pick(&self.audio_codec, profile.audio_codec.as_str())?;
+        for input in self.inputs.iter(){
+            if let Some((protocol, _)) = input.split_once("://"){
+                if !installation.can_read(protocol){
+                    return Err(ClypperError::FFmpegUnsupported(format!("this ffmpeg can't read {} inputs", protocol)));
+                }
+            }
+        }
+        Ok((installation.ffmpeg.clone(), video_codec, audio_codec))
+    }
+
+    ///
+    /// The output's size when a reframe or layout decides it
+    ///
+    fn frame_size(&self) -> Option<(u32, u32)>{
+        match (&self.reframe, &self.layout){
+            (_, Some(layout)) => Some(layout.size),
+            (Some(reframe), None) => Some(reframe.size()),
+            (None, None) => None,
+        }
+    }
+
+    ///
+    /// The reframe or layout followed by the filters added with [`FFmpeg::filter_graph`] and
+    /// the captions in `captions_file`, `None` if there's nothing to filter
+    ///
+    fn build_filter_graph(&self, captions_file: Option<&Path>) -> Result<Option<FilterGraph>, ClypperError>{
+        let mut graph = match (&self.reframe, &self.layout){
+            (Some(_), Some(_)) => return Err(ClypperError::FFmpeg("a clip can be reframed or laid out, not both".to_string())),
+            (Some(reframe), None) => reframe.filter_graph("0:v", "v"),
+            (None, Some(layout)) => {
+                layout.validate()?;
+                layout.filter_graph("0:v", "v")
+            },
+            (None, None) => FilterGraph::new(),
+        };
+        graph.append(self.filter_graph.clone());
+        if let Some(path) = captions_file{
+            let video = match graph.is_empty(){
+                true => None,
+                false => output_map(&graph.validate()?, StreamType::Video)?,
+            };
+            let video = video.map(|label| label.trim_matches(['[', ']']).to_string()).unwrap_or_else(|| "0:v".to_string());
+            graph.chain(&[video.as_str()], vec![Filter::Subtitles{ path: path.to_path_buf(), force_style: None }], &["captioned"]);
+        }
+        Ok((!graph.is_empty()).then_some(graph))
+    }
+
+    ///
+    /// The profile to encode with and where to write the output. An output without an extension
+    /// gets the profile's, one with a different extension is refused rather than written in the
+    /// wrong container.
+    ///
+    fn check_output(&self, output: &str) -> Result<(EncodeProfile, PathBuf), ClypperError>{
+        let mut output = PathBuf::from(output);
+        let requested = Container::from_path(&output);
+        let profile = match (self.profile.clone(), requested){
+            (Some(profile), _) => profile,
+            (None, Some(Container::Webm)) => EncodeProfile::builtin("web").unwrap_or_default(),
+            (None, Some(container)) => EncodeProfile{ container, ..EncodeProfile::default() },
+            (None, None) => EncodeProfile::default(),
+        };
+        match (requested, output.extension()){
+            (_, None) => {
+                output.set_extension(profile.container.extension());
+            },
+            (Some(container), _) if container == profile.container => {},
+            _ => return Err(ClypperError::FFmpeg(format!(
+                "the {} profile writes {} files, not {}", profile.name, profile.container, output.display()
+            ))),
+        }
+        Ok((profile, output))
+    }
+
+    ///
+    /// Starts the encode on a background thread and hands back its [`FFmpegJob`]. Expired inputs
+    /// are refreshed before ffmpeg starts, and a run that fails with 403 is retried once with
+    /// refreshed inputs. The builder is left empty.
+    ///
+    pub fn spawn(&mut self) -> Result<FFmpegJob, ClypperError>{
+        let mut runner = self.prepare()?;
+        let control = runner.control.clone();
+        let handle = thread::spawn(move || {
+            let result = runner.run();
+            if let Some(ref captions_file) = runner.captions_file{
+                let _ = fs::remove_file(captions_file);
+            }
+            let state = match result{
+                Ok(_) => FFmpegState::Finished,
+                Err(ClypperError::Cancelled) => FFmpegState::Cancelled,
+                Err(_) => FFmpegState::Error,
+            };
+            runner.control.set_state(state);
+            result
+        });
+        Ok(FFmpegJob{ control, handle })
+    }
+
+    ///
+    /// Checks the job and moves it into the [`Runner`] that builds and runs its passes, leaving
+    /// the builder empty
+    ///
+    pub(crate) fn prepare(&mut self) -> Result<Runner, ClypperError>{
+        let output = self.output.clone().ok_or_else(|| ClypperError::FFmpeg("no output set".to_string()))?;
+        if self.inputs.is_empty(){
+            return Err(ClypperError::FFmpeg("no inputs set".to_string()));
+        }
+        let (profile, output) = self.check_output(output.as_str())?;
+        let captions_file = self.captions.as_ref().map(|_| {
+            let name = output.file_name().unwrap_or_default().to_string_lossy();
+            output.with_file_name(format!(".{}.ass", name))
+        });
+        let graph = self.build_filter_graph(captions_file.as_deref())?;
+        if graph.is_some() && matches!(self.cut_mode, CutMode::StreamCopy | CutMode::SmartCut){
+            return Err(ClypperError::FFmpegUnsupported("filtering needs the whole clip re-encoded".to_string()));
+        }
+        if self.loudness.is_some() && self.cut_mode == CutMode::StreamCopy{
+            return Err(ClypperError::FFmpegUnsupported("normalizing loudness needs the audio re-encoded".to_string()));
+        }
+        let (filter_graph, video_map, audio_map) = match graph{
+            Some(ref graph) => {
+                let outputs = graph.validate()?;
+                let video_map = output_map(&outputs, StreamType::Video)?.unwrap_or_else(|| "0:v".to_string());
+                let audio_map = output_map(&outputs, StreamType::Audio)?.unwrap_or_else(|| "1:a".to_string());
+                (Some(graph.render()?), video_map, audio_map)
+            },
+            None => (None, "0:v".to_string(), "1:a".to_string()),
+        };
+        //Loudness is measured on the source audio, so nothing else can filter it before loudnorm
+        if self.loudness.is_some() && audio_map != "1:a"{
+            return Err(ClypperError::FFmpegUnsupported("loudness can't be normalized after the audio has been filtered".to_string()));
+        }
+        if self.loudness.is_some() && map_input(audio_map.as_str()).is_none_or(|(input, _)| input >= self.inputs.len()){
+            return Err(ClypperError::FFmpeg(format!("no input for {} to measure the loudness of", audio_map)));
+        }
+        //The passes add loudnorm after the audio, so check the graph they'll run
+        let checked = match self.loudness{
+            Some(target) => {
+                let mut checked = graph.unwrap_or_default();
+                checked.chain(&[audio_map.as_str()], loudnorm_filters(target, None), &[LOUDNORM_LABEL]);
+                checked.validate()?;
+                Some(checked)
+            },
+            None => graph,
+        };
+        let (program, video_codec, audio_codec) = self.check_installation(&profile, checked.as_ref())?;
+        if let (Some((captions, style)), Some(path)) = (&self.captions, &captions_file){
+            fs::write(path, to_ass(captions, style, self.frame_size().unwrap_or(DEFAULT_PLAY_RES)))?;
+        }
+        let ffprobe = self.installation.as_ref()
+            .and_then(|installation| installation.ffprobe.clone())
+            .unwrap_or_else(default_ffprobe);
+        let FFmpeg{ inputs, start_ms, end_ms, metadata, refresh, control, cut_mode, loudness, verification, verify_retries, on_progress_callback, .. } = std::mem::take(self);
+        Ok(Runner{
+            program,
+            ffprobe,
+            profile,
+            cut_mode,
+            filter_graph,
+            video_map,
+            audio_map,
+            loudness,
+            measured: None,
+            loudnorm_report: None,
+            video_codec,
+            audio_codec,
+            inputs,
             start_ms,
             end_ms,
-        }
+            output,
+            metadata,
+            refresh,
+            control,
+            verification,
+            verify_retries,
+            captions_file,
+            on_progress_callback,
+        })
     }
 }
 
-pub struct FFmpeg<'ffmpeg, OnProgressCallback, OnStateChangeCallback> {
-    process: Option<FFmpegHandle>,
-    inputs: Vec<Cow<'ffmpeg, str>>,
-    start_ms: u64,
-    start_ms_str: Cow<'ffmpeg, str>,
-    end_ms: u64,
-    end_ms_str: Cow<'ffmpeg, str>,
-    output: Option<&'ffmpeg str>,
+///
+/// What a finished job produced
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FFmpegOutput{
+    pub path: PathBuf,
+    ///`None` unless the job was asked to [`FFmpeg::verify`] its output
+    pub verification: Option<VerificationReport>,
+    ///`None` unless the job was asked to [`FFmpeg::normalize_loudness`]
+    pub loudness: Option<LoudnessReport>,
+}
+
+///
+/// A running encode, returned by [`FFmpeg::spawn`]
+///
+pub struct FFmpegJob{
+    control: FFmpegControl,
+    handle: JoinHandle<Result<FFmpegOutput, ClypperError>>,
+}
+
+impl FFmpegJob{
+    ///
+    /// Blocks until ffmpeg is done. Fails with [`ClypperError::Cancelled`] if the job was cancelled.
+    ///
+    pub fn wait(self) -> Result<FFmpegOutput, ClypperError>{
+        match self.handle.join(){
+            Ok(result) => result,
+            Err(_) => Err(ClypperError::FFmpeg("ffmpeg job panicked".to_string())),
+        }
+    }
+
+    ///
+    /// The job's current state, without blocking
+    ///
+    pub fn try_status(&self) -> FFmpegState{
+        self.control.status()
+    }
+
+    pub fn is_finished(&self) -> bool{
+        self.handle.is_finished()
+    }
+
+    pub fn cancel(&self) -> Result<(), ClypperError>{
+        self.control.cancel()
+    }
+
+    pub fn pause(&self) -> Result<(), ClypperError>{
+        self.control.pause()
+    }
+
+    pub fn resume(&self) -> Result<(), ClypperError>{
+        self.control.resume()
+    }
+
+    ///
+    /// A cloneable handle for controlling the job from elsewhere, e.g. a UI thread, while this one is waited on
+    ///
+    pub fn control(&self) -> FFmpegControl{
+        self.control.clone()
+    }
+}
 
+#[derive(Default)]
+struct ControlState{
     state: FFmpegState,
-    ffmpeg_thread: Option<FFmpegThread>,
-    errors: Arc<Mutex<Vec<FFmpegError>>>,
+    ///The last progress reported, so resuming can go back to it
+    progress_ms: u64,
+    cancelled: bool,
+    paused: bool,
+    child: Option<Child>,
+}
 
-    on_progress_callback: Option<Box<OnProgressCallback>>,
-    on_state_change_callback: Option<Box<OnStateChangeCallback>>,
+#[derive(Default)]
+struct Shared{
+    inner: Mutex<ControlState>,
+    on_state_change: Mutex<Option<StateChangeCallback>>,
 }
 
-impl<'ffmpeg, OnProgressCallback, OnStateChangeCallback> FFmpeg<'ffmpeg, OnProgressCallback, OnStateChangeCallback>
-where
-    OnProgressCallback: Fn(u64) -> (),
-    OnStateChangeCallback: Fn(FFmpegState) -> (),
-{
-    pub fn new() -> Self{
-        Self{
-            process: None,
-            inputs: vec![],
-            start_ms: 0,
-            start_ms_str: "".into(),
-            end_ms: 0,
-            end_ms_str: "".into(),
-            output: None,
+///
+/// Cancels, pauses and resumes an ffmpeg job from any thread
+///
+#[derive(Clone, Default)]
+pub struct FFmpegControl{
+    shared: Arc<Shared>,
+}
 
-            state: FFmpegState::default(),
-            ffmpeg_thread: None,
-            errors: Arc::default(),
+impl FFmpegControl{
+    pub fn status(&self) -> FFmpegState{
+        self.lock().state.clone()
+    }
 
-            on_progress_callback: None,
-            on_state_change_callback: None,
+    pub fn is_cancelled(&self) -> bool{
+        self.lock().cancelled
+    }
+
+    ///
+    /// Kills ffmpeg and deletes whatever it wrote so far. A job cancelled before ffmpeg starts never starts it.
+    ///
+    pub fn cancel(&self) -> Result<(), ClypperError>{
+        let mut inner = self.lock();
+        if inner.state.is_done(){
+            return Ok(());
+        }
+        inner.cancelled = true;
+        if let Some(ref mut child) = inner.child{
+            //NOTE: The process may have exited on its own in the meantime, which is just as good
+            let _ = child.kill();
         }
+        Ok(())
     }
 
+    ///
+    /// Suspends ffmpeg. Servers may drop the connection if it stays paused for too long.
+    ///
+    pub fn pause(&self) -> Result<(), ClypperError>{
+        let mut inner = self.lock();
+        if inner.state.is_done() || inner.paused{
+            return Ok(());
+        }
+        if let Some(ref child) = inner.child{
+            signal(child, Signal::Stop)?;
+        }
+        inner.paused = true;
+        inner.state = FFmpegState::Paused;
+        drop(inner);
+        self.notify(FFmpegState::Paused);
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<(), ClypperError>{
+        let mut inner = self.lock();
+        if !inner.paused{
+            return Ok(());
+        }
+        if let Some(ref child) = inner.child{
+            signal(child, Signal::Continue)?;
+        }
+        inner.paused = false;
+        inner.state = if inner.child.is_some(){
+            FFmpegState::Downloading(inner.progress_ms)
+        }else{
+            FFmpegState::NotStarted
+        };
+        let state = inner.state.clone();
+        drop(inner);
+        self.notify(state);
+        Ok(())
+    }
+
+    pub(crate) fn set_state(&self, state: FFmpegState){
+        {
+            let mut inner = self.lock();
+            if let FFmpegState::Downloading(progress_ms) = state{
+                inner.progress_ms = progress_ms;
+                //Progress lines still buffered from before a pause shouldn't unpause the job
+                if inner.paused{
+                    return;
+                }
+            }
+            inner.state = state.clone();
+        }
+        self.notify(state);
+    }
+
+    fn notify(&self, state: FFmpegState){
+        if let Some(ref callback) = *self.lock_callback(){
+            callback(state);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ControlState>{
+        //NOTE: Nothing panics while holding the lock, a poisoned one still holds a usable state
+        self.shared.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn lock_callback(&self) -> MutexGuard<'_, Option<StateChangeCallback>>{
+        self.shared.on_state_change.lock().unwrap_or_else(|err| err.into_inner())
+    }
 }
 
-impl<'ffmpeg, OnProgressCallback, OnStateChangeCallback> FFmpeg<'ffmpeg, OnProgressCallback, OnStateChangeCallback>
-where
-    OnProgressCallback: Fn(u64) -> (),
-    OnStateChangeCallback: Fn(FFmpegState) -> (),
-{
-    pub fn state_change_callback(&mut self, callback: OnStateChangeCallback) -> &mut Self{
-        self.on_state_change_callback = Some(Box::new(callback));
-        self
+enum Signal{
+    Stop,
+    Continue,
+}
+
+#[cfg(unix)]
+fn signal(child: &Child, signal: Signal) -> Result<(), ClypperError>{
+    let signal = match signal{
+        Signal::Stop => libc::SIGSTOP,
+        Signal::Continue => libc::SIGCONT,
+    };
+    //SAFETY: kill has no memory safety requirements, the pid is our own child which hasn't been reaped yet
+    if unsafe { libc::kill(child.id() as libc::pid_t, signal) } != 0{
+        return Err(ClypperError::Io(std::io::Error::last_os_error()));
     }
+    Ok(())
+}
 
-    pub fn progress_callback(&mut self, callback: OnProgressCallback) -> &mut Self {
-        self.on_progress_callback = Some(Box::new(callback));
-        self
+#[cfg(not(unix))]
+fn signal(_child: &Child, _signal: Signal) -> Result<(), ClypperError>{
+    Err(ClypperError::Unsupported("pausing ffmpeg is only supported on unix".to_string()))
+}
+
+///
+/// How the runner decided to cut the clip, see [`CutMode`]
+///
+enum CutPlan{
+    Reencode,
+    StreamCopy,
+    SmartCut(CutPoints),
+}
+
+///
+/// One run of ffmpeg. A job is a single pass unless it's smart cut or normalizes loudness.
+///
+pub(crate) struct Pass{
+    command: Command,
+    ///`None` for passes that write nothing
+    output: Option<PathBuf>,
+    ///`None` for passes too quick to be worth reporting on
+    progress: Option<ProgressParser>,
+}
+
+#[cfg(test)]
+impl Pass{
+    pub(crate) fn args(&self) -> Vec<String>{
+        self.command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect()
     }
-    pub fn output(&mut self, output: &'ffmpeg str) -> Result<&mut Self, FFmpegError> {
-        self.output = Some(output);
+}
 
-        Ok(self)
+///
+/// The `-map` for the graph's output of type `kind`, `None` if it has none so the input stream
+/// goes straight through
+///
+fn output_map(outputs: &[(String, StreamType)], kind: StreamType) -> Result<Option<String>, ClypperError>{
+    let mut labels = outputs.iter().filter(|(_, output)| *output == kind);
+    match (labels.next(), labels.next()){
+        (Some(_), Some(_)) => Err(ClypperError::FilterGraph(format!("more than one {} output, only one can be encoded", kind))),
+        (Some((label, _)), None) => Ok(Some(format!("[{}]", label))),
+        _ => Ok(None),
     }
+}
 
-    pub fn time(
-        &mut self,
-        start_ms: u64,
-        end_ms: u64
-    ) -> Result<&mut Self, FFmpegError>{
-        self.start_ms = start_ms;
-        let start_str = format!("{}ms", start_ms);
-        self.start_ms_str = start_str.into();
-        self.end_ms = end_ms;
-        self.end_ms_str = format!("{}ms", end_ms).into();
+///
+/// The input index and stream specifier an input `-map` like `1:a` points at, `None` for graph outputs
+///
+fn map_input(map: &str) -> Option<(usize, &str)>{
+    let (input, stream) = map.split_once(':')?;
+    Some((input.parse().ok()?, stream))
+}
 
-        Ok(self)
+///
+/// `loudnorm` and a resample back to 48kHz, as it always outputs 192kHz
+///
+fn loudnorm_filters(target: LoudnessTarget, measured: Option<LoudnessReport>) -> Vec<Filter>{
+    vec![
+        Filter::Loudnorm{ target, measured },
+        Filter::Custom{ name: "aresample".to_string(), args: "48000".to_string(), inputs: vec![StreamType::Audio], outputs: vec![StreamType::Audio] },
+    ]
+}
+
+///
+/// Everything the job's thread needs, moved out of the [`FFmpeg`] builder
+///
+pub(crate) struct Runner{
+    program: PathBuf,
+    ffprobe: PathBuf,
+    profile: EncodeProfile,
+    cut_mode: CutMode,
+    ///Rendered for `-filter_complex`
+    filter_graph: Option<String>,
+    ///What's mapped into the output, a graph output like `[v]` or a stream like `0:v`
+    video_map: String,
+    audio_map: String,
+    loudness: Option<LoudnessTarget>,
+    ///What the first of the two loudness passes measured
+    measured: Option<LoudnessReport>,
+    ///What `loudnorm` printed in the last pass that ran it
+    loudnorm_report: Option<LoudnessReport>,
+    video_codec: String,
+    audio_codec: String,
+    inputs: Vec<String>,
+    start_ms: u64,
+    end_ms: u64,
+    output: PathBuf,
+    metadata: Vec<(String, String)>,
+    refresh: Option<RefreshCallback>,
+    control: FFmpegControl,
+    verification: Option<Verification>,
+    verify_retries: u32,
+    ///The ASS file the graph burns in, removed once the job is done
+    captions_file: Option<PathBuf>,
+
+    on_progress_callback: Option<ProgressCallback>,
+}
+
+impl Runner{
+    fn run(&mut self) -> Result<FFmpegOutput, ClypperError>{
+        let mut retries = self.verify_retries;
+        loop{
+            self.encode()?;
+            let loudness = self.loudness.and(self.loudnorm_report);
+            let Some(ref verification) = self.verification else{
+                return Ok(FFmpegOutput{ path: self.output.clone(), verification: None, loudness });
+            };
+            let report = verification.verify(&self.ffprobe, &self.output)?;
+            if report.is_ok(){
+                return Ok(FFmpegOutput{ path: self.output.clone(), verification: Some(report), loudness });
+            }
+            if retries == 0{
+                return Err(ClypperError::VerificationFailed(Box::new(report)));
+            }
+            retries -= 1;
+        }
     }
-    pub fn input(
-        &mut self,
-        url: &'ffmpeg str,
-    ) -> Result<&mut Self, FFmpegError> {
-        self.inputs.push(url.into());
 
-        Ok(self)
+    fn encode(&mut self) -> Result<(), ClypperError>{
+        if let Some(expired) = self.inputs.iter().find(|input| is_url_expired(input)){
+            self.refresh(expired.clone())?;
+        }
+        //Measured once, a retried encode is of the same audio
+        if let (Some(target), None) = (self.loudness, self.measured){
+            self.run_pass(|runner| runner.measure_pass(target))?;
+            let measured = self.loudnorm_report.take()
+                .ok_or_else(|| ClypperError::FFmpeg("loudnorm didn't report what it measured".to_string()))?;
+            self.measured = Some(measured);
+        }
+        match self.plan()?{
+            CutPlan::Reencode => self.run_pass(Self::reencode_pass),
+            CutPlan::StreamCopy => self.run_pass(Self::stream_copy_pass),
+            CutPlan::SmartCut(points) => {
+                let parts = self.parts_dir();
+                let result = self.smart_cut(points, parts.as_path());
+                //NOTE: Leftover parts are only clutter, they shouldn't hide how the cut went
+                let _ = fs::remove_dir_all(&parts);
+                result
+            },
+        }
     }
 
-    fn change_state(&mut self, state: FFmpegState){
-        self.state = state;
-        if let Some(ref state_change_callback) = self.on_state_change_callback{
-            state_change_callback(self.state.clone());
+    ///
+    /// Runs the pass `build` makes, building it again with refreshed inputs if ffmpeg gets a 403
+    ///
+    fn run_pass(&mut self, build: impl Fn(&Self) -> Pass) -> Result<(), ClypperError>{
+        if let Some(forbidden) = self.run_once(build(self))?{
+            self.refresh(forbidden)?;
+            if let Some(forbidden) = self.run_once(build(self))?{
+                return Err(ClypperError::StreamExpired(forbidden));
+            }
         }
+        Ok(())
+    }
+
+    ///
+    /// Swaps the inputs for fresh ones, failing with [`ClypperError::StreamExpired`] for `url`
+    /// if there's no way to refresh them or the new ones are no better
+    ///
+    fn refresh(&mut self, url: String) -> Result<(), ClypperError>{
+        let Some(ref mut refresh) = self.refresh else{
+            return Err(ClypperError::StreamExpired(url));
+        };
+        let inputs = refresh()?;
+        if inputs.len() != self.inputs.len(){
+            return Err(ClypperError::FFmpeg(format!("refreshing returned {} inputs, expected {}", inputs.len(), self.inputs.len())));
+        }
+        if let Some(expired) = inputs.iter().find(|input| is_url_expired(input)){
+            return Err(ClypperError::StreamExpired(expired.clone()));
+        }
+        self.inputs = inputs;
+        Ok(())
+    }
+
+    fn plan(&self) -> Result<CutPlan, ClypperError>{
+        match self.cut_mode{
+            CutMode::Reencode => Ok(CutPlan::Reencode),
+            CutMode::Auto if self.filter_graph.is_some() => Ok(CutPlan::Reencode),
+            CutMode::StreamCopy => {
+                let video = probe_stream(&self.ffprobe, self.inputs[0].as_str(), "v:0")?;
+                let audio = probe_stream(&self.ffprobe, self.inputs[self.inputs.len() - 1].as_str(), "a:0")?;
+                let container = self.profile.container;
+                for codec in [video.codec_name, audio.codec_name]{
+                    if !container.can_hold(codec.as_str()){
+                        return Err(ClypperError::FFmpegUnsupported(format!("{} streams can't be copied into {}", codec, container)));
+                    }
+                }
+                Ok(CutPlan::StreamCopy)
+            },
+            CutMode::SmartCut => self.smart_cut_points().map(CutPlan::SmartCut),
+            CutMode::Auto => match self.smart_cut_points(){
+                Ok(points) => Ok(CutPlan::SmartCut(points)),
+                Err(ClypperError::Cancelled) => Err(ClypperError::Cancelled),
+                Err(_) => Ok(CutPlan::Reencode),
+            },
+        }
+    }
+
+    ///
+    /// Where a smart cut can switch to copying, failing if the source can't be smart cut with this profile
+    ///
+    fn smart_cut_points(&self) -> Result<CutPoints, ClypperError>{
+        let video_input = self.inputs[0].as_str();
+        let video = probe_stream(&self.ffprobe, video_input, "v:0")?;
+        //The encoded head and tail are joined to the copied middle, so they have to match it
+        let encodes_to = codec_name(self.video_codec.as_str()).unwrap_or_default();
+        if video.codec_name != encodes_to{
+            return Err(ClypperError::FFmpegUnsupported(format!("can't smart cut {} video with {}", video.codec_name, self.video_codec)));
+        }
+        if let (Some(wanted), Some(source)) = (self.profile.pixel_format.as_ref(), video.pix_fmt.as_ref()){
+            if wanted != source{
+                return Err(ClypperError::FFmpegUnsupported(format!("can't smart cut {} video into {}", source, wanted)));
+            }
+        }
+        if !self.profile.container.can_hold(video.codec_name.as_str()){
+            return Err(ClypperError::FFmpegUnsupported(format!("{} streams can't be copied into {}", video.codec_name, self.profile.container)));
+        }
+        if self.control.is_cancelled(){
+            return Err(ClypperError::Cancelled);
+        }
+        let keyframes = probe_keyframes(&self.ffprobe, video_input, self.start_ms, self.end_ms)?;
+        CutPoints::find(&keyframes, self.start_ms, self.end_ms)
+            .ok_or_else(|| ClypperError::FFmpegUnsupported("the clip is too short to have keyframes to copy between".to_string()))
+    }
+
+    ///
+    /// Encodes the head and tail of the clip and copies its middle into separate video-only
+    /// parts, then joins them and encodes the audio into the output
+    ///
+    fn smart_cut(&mut self, points: CutPoints, parts: &Path) -> Result<(), ClypperError>{
+        fs::create_dir_all(parts)?;
+        let CutPoints{ first_keyframe_us, last_keyframe_us } = points;
+        let (start_us, end_us) = (self.start_ms * 1000, self.end_ms * 1000);
+        //h264 and hevc carry their parameter sets in-band in mpegts, so parts encoded with
+        //different settings still decode once joined
+        let extension = match codec_name(self.video_codec.as_str()){
+            Some("h264" | "hevc") => "ts",
+            _ => "mkv",
+        };
+        let mut part_paths = vec![];
+        let pieces = [
+            (start_us, first_keyframe_us, false),
+            (first_keyframe_us, last_keyframe_us, true),
+            (last_keyframe_us, end_us, false),
+        ];
+        for (i, (from_us, to_us, copy)) in pieces.into_iter().enumerate(){
+            if from_us >= to_us{
+                continue;
+            }
+            let path = parts.join(format!("{}.{}", i, extension));
+            self.run_pass(|runner| runner.video_part_pass(from_us, to_us, copy, path.clone()))?;
+            part_paths.push(path);
+        }
+
+        let list = parts.join("parts.txt");
+        let mut file = fs::File::create(&list)?;
+        for path in part_paths.iter(){
+            //Paths are relative to the list. The concat demuxer quotes like a shell, with ' escaped as '\''
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            writeln!(file, "file '{}'", name.replace('\'', "'\\''"))?;
+        }
+        drop(file);
+        self.run_pass(|runner| runner.join_pass(list.as_path()))
+    }
+
+    ///
+    /// Parts are written next to the output so joining them doesn't cross filesystems
+    ///
+    fn parts_dir(&self) -> PathBuf{
+        let name = self.output.file_name().unwrap_or_default().to_string_lossy();
+        self.output.with_file_name(format!(".{}.parts", name))
+    }
+
+    fn command(&self) -> Command{
+        let mut command = Command::new(&self.program);
+        command.args(["-hide_banner", "-nostats", "-progress", "pipe:2", "-y"]);
+        command
     }
 
-    pub fn spawn(&mut self) -> Result<(), FFmpegError> {
-        let mut command = Command::new("ffmpeg");
-        for input in self.inputs.clone(){
-            command.args([
-                "-ss", self.start_ms_str.as_ref(), 
-                "-to", self.end_ms_str.as_ref(), 
-                "-i", input.to_string().as_str()
-            ]);
+    fn cut_inputs(&self, command: &mut Command){
+        let start = format!("{}ms", self.start_ms);
+        let end = format!("{}ms", self.end_ms);
+        for input in self.inputs.iter(){
+            command.args(["-ss", start.as_str(), "-to", end.as_str(), "-i", input.as_str()]);
+        }
+    }
+
+    ///
+    /// Tags and container options, then the output itself
+    ///
+    fn finish(&self, mut command: Command, progress: Option<ProgressParser>) -> Pass{
+        for (key, value) in self.metadata.iter(){
+            command.args(["-metadata", format!("{}={}", key, value).as_str()]);
         }
         command
-            .args(["-hide_banner", "-progress", "pipe:2", "-y", "-map", "0:v", "-map", "1:a",  "-c:v", "libx264", "-c:a", "aac"])
-            .args(self.output.clone())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-        //for arg in command.get_args(){
-        //    println!("{:?}", arg);
-        //}
-        self.change_state(FFmpegState::Starting);
-
-        let process = command.spawn().unwrap();
-        let stderr = process.stderr.ok_or_else(|| FFmpegError {
-            message: "Failed to get stderr from ffmpeg process".to_string(),
-            state: self.state.clone(),
-        })?;
-        {
-            let errors = self.errors.clone();
-            let buffer = BufReader::new(stderr);
-            let (ffmpeg_send, ffmpeg_recv) = std::sync::mpsc::channel();
-            self.ffmpeg_thread = Some(std::thread::spawn(move || {
-                let re = Regex::new(r#"\btime=(\d+):(\d+):(\d+)\.(\d+)"#).unwrap();
-                buffer
-                    .lines()
-                    .filter_map(|line| line.ok())
-                    .for_each(|line| {
-                        let cap = re
-                            .captures(line.as_str())
-                            .map(|capture| {
-                                let hr = capture.get(1).unwrap().as_str();
-                                let min = capture.get(2).unwrap().as_str();
-                                let sec = capture.get(3).unwrap().as_str();
-                                let ms = capture.get(4).unwrap().as_str();
-                                (hr, min, sec, ms)
-                            })
-                            .map(|(hr, min, sec, ms)| 
-                                (
-                                    hr.parse::<u64>().unwrap(), 
-                                    min.parse::<u64>().unwrap(), 
-                                    sec.parse::<u64>().unwrap(), 
-                                    ms.parse::<u64>().unwrap())
-                                )
-                            .map(|(hr, min, sec, ms)| {
-                                (hr * 60 * 60 * 1000) + (min * 60 * 1000) + (sec * 1000) + ms
-                            });
-                        if let Some(time) = cap {
-                            let state = FFmpegState::Downloading(time);
-                            if let Err(_err) = ffmpeg_send.send(state.clone()) {
-                                let error = FFmpegError {
-                                    message: "FFmpeg Channel unexpectedly closed".to_string(),
-                                    state: state.clone(),
-                                };
-                                //NOTE: This is not a problem because errors only occur when another
-                                //holder of the mutex panics
-                                let mut errors = errors.lock().unwrap();
-                                errors.push(error);
-                                return;
-                            }
-                        }
-                    });
-                ffmpeg_send.send(FFmpegState::Finished).unwrap();
-                Ok(())
-            }));
-            while self.state != FFmpegState::Finished{
-                let recv = ffmpeg_recv.recv();
-                match recv{
-                    Ok(state) => {
-                        self.change_state(state.clone());
-                        if let FFmpegState::Downloading(time) = state{
-                            if let Some(ref cb) = self.on_progress_callback{
-                                cb(time);
-                            }
-                        }
-                    },
-                    Err(_) => break
+            .args(self.profile.container_args())
+            .arg(&self.output);
+        Pass{ command, output: Some(self.output.clone()), progress }
+    }
+
+    ///
+    /// The graph with `loudnorm` after the audio and the audio's new `-map`, or both as they are
+    /// without a loudness target
+    ///
+    fn normalized_audio(&self) -> (Option<String>, String){
+        let Some(target) = self.loudness else{
+            return (self.filter_graph.clone(), self.audio_map.clone());
+        };
+        let filters: Vec<String> = loudnorm_filters(target, self.measured).iter().map(Filter::render).collect();
+        let chain = format!("[{}]{}[{}]", self.audio_map, filters.join(","), LOUDNORM_LABEL);
+        let graph = match self.filter_graph{
+            Some(ref graph) => format!("{};{}", graph, chain),
+            None => chain,
+        };
+        (Some(graph), format!("[{}]", LOUDNORM_LABEL))
+    }
+
+    ///
+    /// Runs only the clip's audio through `loudnorm` into nothing, for it to measure. The video
+    /// and its graph aren't needed, nothing filters the audio before loudnorm does.
+    ///
+    pub(crate) fn measure_pass(&self, target: LoudnessTarget) -> Pass{
+        //spawn() made sure the audio comes straight from an input that exists
+        let (input, stream) = map_input(self.audio_map.as_str()).unwrap_or((1, "a"));
+        let mut command = self.command();
+        command.args(["-ss", format!("{}ms", self.start_ms).as_str(), "-to", format!("{}ms", self.end_ms).as_str(), "-i", self.inputs[input].as_str()])
+            .args(["-map", format!("0:{}", stream).as_str(), "-af", Filter::Loudnorm{ target, measured: None }.render().as_str(), "-f", "null", "-"]);
+        Pass{ command, output: None, progress: None }
+    }
+
+    fn progress(&self) -> ProgressParser{
+        ProgressParser::new(self.end_ms.saturating_sub(self.start_ms))
+    }
+
+    pub(crate) fn reencode_pass(&self) -> Pass{
+        let mut command = self.command();
+        self.cut_inputs(&mut command);
+        let (graph, audio_map) = self.normalized_audio();
+        if let Some(graph) = graph{
+            command.args(["-filter_complex", graph.as_str()]);
+        }
+        command.args(["-map", self.video_map.as_str(), "-map", audio_map.as_str()])
+            .args(self.profile.video_args(self.video_codec.as_str()))
+            .args(self.profile.audio_args(self.audio_codec.as_str()));
+        self.finish(command, Some(self.progress()))
+    }
+
+    pub(crate) fn stream_copy_pass(&self) -> Pass{
+        let mut command = self.command();
+        self.cut_inputs(&mut command);
+        command.args(["-map", "0:v", "-map", "1:a", "-c", "copy", "-avoid_negative_ts", "make_zero"]);
+        self.finish(command, Some(self.progress()))
+    }
+
+    pub(crate) fn video_part_pass(&self, from_us: u64, to_us: u64, copy: bool, output: PathBuf) -> Pass{
+        let mut command = self.command();
+        command.args(["-ss", format!("{}us", from_us).as_str(), "-to", format!("{}us", to_us).as_str(), "-i", self.inputs[0].as_str()])
+            .args(["-map", "0:v", "-an"]);
+        if copy{
+            command.args(["-c:v", "copy"]);
+        }else{
+            command.args(self.profile.video_args(self.video_codec.as_str()));
+        }
+        command.arg(&output);
+        Pass{
+            command,
+            output: Some(output),
+            progress: Some(self.progress().partial((from_us / 1000).saturating_sub(self.start_ms))),
+        }
+    }
+
+    pub(crate) fn join_pass(&self, list: &Path) -> Pass{
+        let audio = self.inputs[self.inputs.len() - 1].as_str();
+        let mut command = self.command();
+        command.args(["-f", "concat", "-safe", "0", "-i"])
+            .arg(list)
+            .args(["-ss", format!("{}ms", self.start_ms).as_str(), "-to", format!("{}ms", self.end_ms).as_str(), "-i", audio]);
+        //Smart cuts never have a graph, so the audio is the second input here as well
+        let (graph, audio_map) = self.normalized_audio();
+        if let Some(graph) = graph{
+            command.args(["-filter_complex", graph.as_str()]);
+        }
+        command.args(["-map", "0:v", "-map", audio_map.as_str(), "-c:v", "copy"])
+            .args(self.profile.audio_args(self.audio_codec.as_str()));
+        //Only the audio is encoded here, which is quick next to the video
+        self.finish(command, None)
+    }
+
+    fn run_once(&mut self, pass: Pass) -> Result<Option<String>, ClypperError>{
+        let Pass{ mut command, output, mut progress } = pass;
+        let stderr = {
+            let mut inner = self.control.lock();
+            if inner.cancelled{
+                return Err(ClypperError::Cancelled);
+            }
+            let mut child = command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|err| match err.kind(){
+                    std::io::ErrorKind::NotFound => ClypperError::FFmpegMissing(self.program.display().to_string()),
+                    _ => ClypperError::FFmpegSpawn(err),
+                })?;
+            if inner.paused{
+                signal(&child, Signal::Stop)?;
+            }
+            let stderr = child.stderr.take();
+            inner.child = Some(child);
+            stderr
+        };
+        if !self.control.lock().paused{
+            self.control.set_state(FFmpegState::Starting);
+        }
+        let stderr = stderr.ok_or_else(|| ClypperError::FFmpeg(
+            "Failed to get stderr from ffmpeg process".to_string()
+        ))?;
+
+        let mut diagnostics = Diagnostics::new(self.inputs.clone(), output.as_ref().map(|output| output.to_string_lossy().into_owned()).unwrap_or_default());
+        let mut loudnorm = LoudnessReportParser::new();
+        let mut loudnorm_report = None;
+        for line in BufReader::new(stderr).lines(){
+            let line = line?;
+            if !ProgressParser::is_progress_line(line.as_str()){
+                //Read from the whole stream, the diagnostics only keep its last lines
+                if let Some(report) = loudnorm.feed(line.as_str()){
+                    loudnorm_report = Some(report);
+                }
+                diagnostics.push(line);
+                continue;
+            }
+            if let Some(report) = progress.as_mut().and_then(|parser| parser.feed(line.as_str())){
+                self.control.set_state(FFmpegState::Downloading(report.out_time_ms));
+                if let Some(ref cb) = self.on_progress_callback{
+                    cb(report);
                 }
             }
         }
-        Ok(())
+
+        //stderr only closes once ffmpeg exits, so this doesn't block for long
+        let (child, cancelled) = {
+            let mut inner = self.control.lock();
+            (inner.child.take(), inner.cancelled)
+        };
+        let status = match child{
+            Some(mut child) => child.wait()?,
+            None => return Err(ClypperError::FFmpeg("ffmpeg process went missing".to_string())),
+        };
+        if cancelled{
+            if let Some(output) = output.filter(|output| output.exists()){
+                fs::remove_file(&output)?;
+            }
+            return Err(ClypperError::Cancelled);
+        }
+        if status.success(){
+            self.loudnorm_report = loudnorm_report.transpose()?;
+            return Ok(None);
+        }
+        match diagnostics.forbidden_input(){
+            Some(forbidden) => Ok(Some(forbidden)),
+            None => Err(diagnostics.into_error(status)),
+        }
     }
 }
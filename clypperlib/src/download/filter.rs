@@ -0,0 +1,516 @@
+use std::{fmt::Display, path::PathBuf};
+
+use crate::error::ClypperError;
+
+use super::loudness::{LoudnessReport, LoudnessTarget};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StreamType{
+    Video,
+    Audio,
+}
+
+impl Display for StreamType{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Self::Video => write!(f, "video"),
+            Self::Audio => write!(f, "audio"),
+        }
+    }
+}
+
+///
+/// How [`Filter::Scale`] treats the source's aspect ratio
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScaleFit{
+    ///Scale to exactly the given size
+    #[default]
+    Stretch,
+    ///Keep the aspect ratio, growing until both sides cover the size
+    Cover,
+    ///Keep the aspect ratio, shrinking until both sides fit in the size
+    Contain,
+}
+
+///
+/// One ffmpeg filter. Sizes and positions are ffmpeg expressions, e.g. `1080`, `iw/2` or
+/// `(W-w)/2`, and are escaped as needed when rendered.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter{
+    Scale{
+        width: String,
+        height: String,
+        fit: ScaleFit,
+    },
+    Crop{
+        width: String,
+        height: String,
+        x: String,
+        y: String,
+    },
+    Pad{
+        width: String,
+        height: String,
+        x: String,
+        y: String,
+        color: String,
+    },
+    ///Draws its second input over its first
+    Overlay{
+        x: String,
+        y: String,
+    },
+    DrawText{
+        text: String,
+        x: String,
+        y: String,
+        font_size: u32,
+        color: String,
+        font_file: Option<PathBuf>,
+        ///Draws a box of this color behind the text
+        box_color: Option<String>,
+    },
+    ///A rectangle, filled unless `thickness` is given
+    DrawBox{
+        x: String,
+        y: String,
+        width: String,
+        height: String,
+        color: String,
+        thickness: Option<u32>,
+    },
+    BoxBlur{
+        radius: u32,
+        power: u32,
+    },
+    ///Burns in a subtitle file
+    Subtitles{
+        path: PathBuf,
+        ///ASS style overrides, e.g. `Fontsize=24,Outline=2`
+        force_style: Option<String>,
+    },
+    SetSar(String),
+    Fps(String),
+    ///Speeds audio up or down without changing its pitch, 0.5 to 100
+    ATempo(f64),
+    ///A factor like `0.5` or a gain like `-3dB`
+    Volume(String),
+    ///EBU R128 loudness normalisation. On its own it adjusts the gain as it goes, given what a
+    ///first pass over the same audio measured it can apply one gain to all of it. Prints a
+    ///[`LoudnessReport`] when done.
+    Loudnorm{
+        target: LoudnessTarget,
+        measured: Option<LoudnessReport>,
+    },
+    ///Joins `segments` pieces, each with `video` video and `audio` audio streams, one after another
+    Concat{
+        segments: u32,
+        video: u32,
+        audio: u32,
+    },
+    ///Copies a stream into `outputs` identical ones
+    Split{
+        kind: StreamType,
+        outputs: u32,
+    },
+    ///Anything else. `args` is rendered as is, so it has to be escaped already.
+    Custom{
+        name: String,
+        args: String,
+        inputs: Vec<StreamType>,
+        outputs: Vec<StreamType>,
+    },
+}
+
+impl Filter{
+    pub fn scale(width: impl ToString, height: impl ToString) -> Self{
+        Self::Scale{ width: width.to_string(), height: height.to_string(), fit: ScaleFit::Stretch }
+    }
+
+    pub fn scale_fit(width: impl ToString, height: impl ToString, fit: ScaleFit) -> Self{
+        Self::Scale{ width: width.to_string(), height: height.to_string(), fit }
+    }
+
+    pub fn crop(width: impl ToString, height: impl ToString, x: impl ToString, y: impl ToString) -> Self{
+        Self::Crop{ width: width.to_string(), height: height.to_string(), x: x.to_string(), y: y.to_string() }
+    }
+
+    pub fn pad(width: impl ToString, height: impl ToString, x: impl ToString, y: impl ToString, color: impl ToString) -> Self{
+        Self::Pad{ width: width.to_string(), height: height.to_string(), x: x.to_string(), y: y.to_string(), color: color.to_string() }
+    }
+
+    pub fn overlay(x: impl ToString, y: impl ToString) -> Self{
+        Self::Overlay{ x: x.to_string(), y: y.to_string() }
+    }
+
+    ///
+    /// Fills the whole frame with `color`
+    ///
+    pub fn fill(color: impl ToString) -> Self{
+        Self::DrawBox{
+            x: "0".to_string(),
+            y: "0".to_string(),
+            width: "iw".to_string(),
+            height: "ih".to_string(),
+            color: color.to_string(),
+            thickness: None,
+        }
+    }
+
+    pub fn name(&self) -> &str{
+        match self{
+            Self::Scale{ .. } => "scale",
+            Self::Crop{ .. } => "crop",
+            Self::Pad{ .. } => "pad",
+            Self::Overlay{ .. } => "overlay",
+            Self::DrawText{ .. } => "drawtext",
+            Self::DrawBox{ .. } => "drawbox",
+            Self::BoxBlur{ .. } => "boxblur",
+            Self::Subtitles{ .. } => "subtitles",
+            Self::SetSar(_) => "setsar",
+            Self::Fps(_) => "fps",
+            Self::ATempo(_) => "atempo",
+            Self::Volume(_) => "volume",
+            Self::Loudnorm{ .. } => "loudnorm",
+            Self::Concat{ .. } => "concat",
+            Self::Split{ kind: StreamType::Video, .. } => "split",
+            Self::Split{ kind: StreamType::Audio, .. } => "asplit",
+            Self::Custom{ name, .. } => name.as_str(),
+        }
+    }
+
+    pub fn inputs(&self) -> Vec<StreamType>{
+        use StreamType::*;
+        match self{
+            Self::Overlay{ .. } => vec![Video, Video],
+            Self::ATempo(_) | Self::Volume(_) | Self::Loudnorm{ .. } => vec![Audio],
+            Self::Concat{ segments, video, audio } => (0..*segments)
+                .flat_map(|_| std::iter::repeat_n(Video, *video as usize).chain(std::iter::repeat_n(Audio, *audio as usize)))
+                .collect(),
+            Self::Split{ kind, .. } => vec![*kind],
+            Self::Custom{ inputs, .. } => inputs.clone(),
+            _ => vec![Video],
+        }
+    }
+
+    pub fn outputs(&self) -> Vec<StreamType>{
+        use StreamType::*;
+        match self{
+            Self::ATempo(_) | Self::Volume(_) | Self::Loudnorm{ .. } => vec![Audio],
+            Self::Concat{ video, audio, .. } => std::iter::repeat_n(Video, *video as usize)
+                .chain(std::iter::repeat_n(Audio, *audio as usize))
+                .collect(),
+            Self::Split{ kind, outputs } => vec![*kind; *outputs as usize],
+            Self::Custom{ outputs, .. } => outputs.clone(),
+            _ => vec![Video],
+        }
+    }
+
+    ///
+    /// Options, by name or by position
+    ///
+    fn args(&self) -> Vec<(Option<&'static str>, String)>{
+        let keyed = |pairs: &[(&'static str, &String)]| pairs.iter().map(|(key, value)| (Some(*key), value.to_string())).collect::<Vec<_>>();
+        match self{
+            Self::Scale{ width, height, fit } => {
+                let mut args = keyed(&[("w", width), ("h", height)]);
+                match fit{
+                    ScaleFit::Stretch => {},
+                    ScaleFit::Cover => args.push((Some("force_original_aspect_ratio"), "increase".to_string())),
+                    ScaleFit::Contain => args.push((Some("force_original_aspect_ratio"), "decrease".to_string())),
+                }
+                args
+            },
+            Self::Crop{ width, height, x, y } => keyed(&[("w", width), ("h", height), ("x", x), ("y", y)]),
+            Self::Pad{ width, height, x, y, color } => keyed(&[("w", width), ("h", height), ("x", x), ("y", y), ("color", color)]),
+            Self::Overlay{ x, y } => keyed(&[("x", x), ("y", y)]),
+            Self::DrawText{ text, x, y, font_size, color, font_file, box_color } => {
+                let mut args = vec![];
+                if let Some(font_file) = font_file{
+                    args.push((Some("fontfile"), font_file.to_string_lossy().into_owned()));
+                }
+                //drawtext expands `%{...}` in its text and drops lone backslashes, a third level to escape
+                let text = escape_chars(text, "%\\");
+                args.extend(keyed(&[("text", &text), ("x", x), ("y", y), ("fontsize", &font_size.to_string()), ("fontcolor", color)]));
+                if let Some(box_color) = box_color{
+                    args.extend(keyed(&[("box", &"1".to_string()), ("boxcolor", box_color)]));
+                }
+                args
+            },
+            Self::DrawBox{ x, y, width, height, color, thickness } => {
+                let thickness = thickness.map(|thickness| thickness.to_string()).unwrap_or_else(|| "fill".to_string());
+                keyed(&[("x", x), ("y", y), ("w", width), ("h", height), ("color", color), ("t", &thickness)])
+            },
+            Self::BoxBlur{ radius, power } => keyed(&[("luma_radius", &radius.to_string()), ("luma_power", &power.to_string())]),
+            Self::Subtitles{ path, force_style } => {
+                let mut args = vec![(Some("filename"), path.to_string_lossy().into_owned())];
+                if let Some(force_style) = force_style{
+                    args.push((Some("force_style"), force_style.clone()));
+                }
+                args
+            },
+            Self::SetSar(sar) => vec![(None, sar.clone())],
+            Self::Fps(fps) => vec![(None, fps.clone())],
+            Self::ATempo(tempo) => vec![(None, tempo.to_string())],
+            Self::Volume(volume) => vec![(None, volume.clone())],
+            Self::Loudnorm{ target, measured } => {
+                let mut args = vec![
+                    (Some("I"), target.integrated.to_string()),
+                    (Some("TP"), target.true_peak.to_string()),
+                    (Some("LRA"), target.range.to_string()),
+                ];
+                //Silence measures as -inf, which ffmpeg won't take back, so it gets the one pass
+                if let Some(measured) = measured.filter(|measured| measured.input.is_finite()){
+                    let input = measured.input;
+                    args.extend([
+                        (Some("measured_I"), input.integrated.clamp(-99.0, 0.0).to_string()),
+                        (Some("measured_TP"), input.true_peak.clamp(-99.0, 99.0).to_string()),
+                        (Some("measured_LRA"), input.range.clamp(0.0, 99.0).to_string()),
+                        (Some("measured_thresh"), input.threshold.clamp(-99.0, 0.0).to_string()),
+                        (Some("offset"), measured.target_offset.clamp(-99.0, 99.0).to_string()),
+                        (Some("linear"), "true".to_string()),
+                    ]);
+                }
+                args.push((Some("print_format"), "json".to_string()));
+                args
+            },
+            Self::Concat{ segments, video, audio } => vec![
+                (Some("n"), segments.to_string()),
+                (Some("v"), video.to_string()),
+                (Some("a"), audio.to_string()),
+            ],
+            Self::Split{ outputs, .. } => vec![(None, outputs.to_string())],
+            Self::Custom{ .. } => vec![],
+        }
+    }
+
+    fn check(&self) -> Result<(), String>{
+        match self{
+            Self::ATempo(tempo) if !(0.5..=100.0).contains(tempo) => Err(format!("atempo {} is outside 0.5 to 100", tempo)),
+            Self::Loudnorm{ target, .. } => target.check(),
+            Self::Concat{ segments: 0, .. } => Err("concat needs at least one segment".to_string()),
+            Self::Concat{ video: 0, audio: 0, .. } => Err("concat needs at least one stream per segment".to_string()),
+            Self::Split{ outputs: 0, .. } => Err("split needs at least one output".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn render(&self) -> String{
+        if let Self::Custom{ name, args, .. } = self{
+            return if args.is_empty() { name.clone() } else { format!("{}={}", name, args) };
+        }
+        let args: Vec<String> = self.args().into_iter()
+            .map(|(key, value)| match key{
+                Some(key) => format!("{}={}", key, escape(value.as_str())),
+                None => escape(value.as_str()),
+            })
+            .collect();
+        if args.is_empty(){
+            return self.name().to_string();
+        }
+        format!("{}={}", self.name(), args.join(":"))
+    }
+}
+
+///
+/// Escapes an option value for the two parsers it goes through. The graph parser splits the
+/// graph on `[ ] , ;` and unescapes it, then the filter's option parser splits what's left on
+/// `:` and unescapes it again, so each level gets its own backslashes.
+///
+fn escape(value: &str) -> String{
+    escape_chars(escape_chars(value, "':\\").as_str(), "[],;'\\")
+}
+
+///
+/// Puts a backslash before every character of `value` found in `special`
+///
+fn escape_chars(value: &str, special: &str) -> String{
+    value.chars().fold(String::with_capacity(value.len()), |mut escaped, c|{
+        if special.contains(c){
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+///
+/// Filters run one after another between labelled inputs and outputs, `[0:v]crop=...,scale=...[v]`
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterChain{
+    pub inputs: Vec<String>,
+    pub filters: Vec<Filter>,
+    pub outputs: Vec<String>,
+}
+
+///
+/// A `-filter_complex` graph, built chain by chain and checked before it's rendered: every
+/// label is produced before it's used and used once, and stream types line up.
+///
+/// Inputs are either ffmpeg's stream specifiers, like `0:v` for the first input's video, or
+/// labels produced by an earlier chain. Labels nothing uses are the graph's outputs.
+///
+/// ```
+/// use clypperlib::download::filter::{Filter, FilterGraph};
+///
+/// let mut graph = FilterGraph::new();
+/// graph.chain(&["0:v"], vec![Filter::crop("ih*9/16", "ih", "(iw-ow)/2", "0"), Filter::scale(1080, 1920)], &["v"])
+///     .chain(&["1:a"], vec![Filter::Volume("-3dB".to_string())], &["a"]);
+/// assert_eq!(
+///     graph.render().unwrap(),
+///     "[0:v]crop=w=ih*9/16:h=ih:x=(iw-ow)/2:y=0,scale=w=1080:h=1920[v];[1:a]volume=-3dB[a]",
+/// );
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FilterGraph{
+    chains: Vec<FilterChain>,
+}
+
+impl FilterGraph{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn chain(&mut self, inputs: &[&str], filters: Vec<Filter>, outputs: &[&str]) -> &mut Self{
+        self.chains.push(FilterChain{
+            inputs: inputs.iter().map(|input| input.to_string()).collect(),
+            filters,
+            outputs: outputs.iter().map(|output| output.to_string()).collect(),
+        });
+        self
+    }
+
+    ///
+    /// Adds `other`'s chains after this graph's
+    ///
+    pub fn append(&mut self, other: FilterGraph) -> &mut Self{
+        self.chains.extend(other.chains);
+        self
+    }
+
+    pub fn chains(&self) -> &[FilterChain]{
+        &self.chains
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.chains.is_empty()
+    }
+
+    ///
+    /// The names of the ffmpeg filters the graph uses, to check an installation has them
+    ///
+    pub fn filter_names(&self) -> Vec<&str>{
+        let mut names: Vec<&str> = self.chains.iter()
+            .flat_map(|chain| chain.filters.iter().map(Filter::name))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    ///
+    /// Checks the graph fits together, returning its outputs: the labels no chain uses
+    ///
+    pub fn validate(&self) -> Result<Vec<(String, StreamType)>, ClypperError>{
+        let invalid = |message: String| Err(ClypperError::FilterGraph(message));
+        //Produced but not used yet, in the order they were produced
+        let mut open: Vec<(String, StreamType)> = vec![];
+        for (i, chain) in self.chains.iter().enumerate(){
+            let (Some(first), Some(last)) = (chain.filters.first(), chain.filters.last()) else{
+                return invalid(format!("chain {} has no filters", i));
+            };
+            for filter in chain.filters.iter(){
+                if let Err(message) = filter.check(){
+                    return invalid(message);
+                }
+            }
+            for pair in chain.filters.windows(2){
+                let (outputs, inputs) = (pair[0].outputs(), pair[1].inputs());
+                if outputs.len() != 1 || inputs.len() != 1 || outputs != inputs{
+                    return invalid(format!("{} can't feed {} in the same chain, label the streams between them", pair[0].name(), pair[1].name()));
+                }
+            }
+
+            let expected = first.inputs();
+            if chain.inputs.len() != expected.len(){
+                return invalid(format!("{} takes {} inputs, got {}", first.name(), expected.len(), chain.inputs.len()));
+            }
+            for (label, expected) in chain.inputs.iter().zip(expected){
+                let actual = match stream_specifier(label){
+                    Some(kind) => kind,
+                    None => match open.iter().position(|(open, _)| open == label){
+                        Some(index) => open.remove(index).1,
+                        None if self.produces(label) => return invalid(format!("[{}] is used more than once, or before it's produced", label)),
+                        None => return invalid(format!("nothing produces [{}]", label)),
+                    },
+                };
+                if actual != expected{
+                    return invalid(format!("{} takes {} but [{}] is {}", first.name(), expected, label, actual));
+                }
+            }
+
+            let produced = last.outputs();
+            if chain.outputs.len() != produced.len(){
+                return invalid(format!("{} has {} outputs, got {} labels", last.name(), produced.len(), chain.outputs.len()));
+            }
+            for (label, kind) in chain.outputs.iter().zip(produced){
+                if stream_specifier(label).is_some(){
+                    return invalid(format!("[{}] is an input stream, it can't be an output label", label));
+                }
+                if label.is_empty() || label.contains(|c: char| !(c.is_ascii_alphanumeric() || c == '_')){
+                    return invalid(format!("[{}] isn't a valid label", label));
+                }
+                if open.iter().any(|(open, _)| open == label) || self.chains[..i].iter().any(|chain| chain.outputs.contains(label)){
+                    return invalid(format!("[{}] is produced more than once", label));
+                }
+                open.push((label.clone(), kind));
+            }
+        }
+        Ok(open)
+    }
+
+    ///
+    /// The graph as `-filter_complex` takes it
+    ///
+    pub fn render(&self) -> Result<String, ClypperError>{
+        self.validate()?;
+        let chains: Vec<String> = self.chains.iter()
+            .map(|chain| {
+                let inputs: String = chain.inputs.iter().map(|input| format!("[{}]", input)).collect();
+                let filters: Vec<String> = chain.filters.iter().map(Filter::render).collect();
+                let outputs: String = chain.outputs.iter().map(|output| format!("[{}]", output)).collect();
+                format!("{}{}{}", inputs, filters.join(","), outputs)
+            })
+            .collect();
+        Ok(chains.join(";"))
+    }
+
+    fn produces(&self, label: &str) -> bool{
+        self.chains.iter().any(|chain| chain.outputs.iter().any(|output| output == label))
+    }
+}
+
+///
+/// The stream type of an input stream specifier like `0:v` or `1:a:0`, `None` for other labels
+///
+fn stream_specifier(label: &str) -> Option<StreamType>{
+    let mut parts = label.split(':');
+    let (Some(input), Some(kind)) = (parts.next(), parts.next()) else{
+        return None;
+    };
+    if input.is_empty() || !input.chars().all(|c| c.is_ascii_digit()){
+        return None;
+    }
+    if !parts.all(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit())){
+        return None;
+    }
+    match kind{
+        "v" | "V" => Some(StreamType::Video),
+        "a" => Some(StreamType::Audio),
+        _ => None,
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::HashSet,
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::error::ClypperError;
+
+///Overrides where ffmpeg is looked for
+pub const FFMPEG_ENV: &str = "CLYPPER_FFMPEG";
+///Overrides where ffprobe is looked for
+pub const FFPROBE_ENV: &str = "CLYPPER_FFPROBE";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EncoderKind{
+    Video,
+    Audio,
+    Subtitle,
+}
+
+///
+/// An entry from `ffmpeg -encoders`
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encoder{
+    pub name: String,
+    pub kind: EncoderKind,
+    pub description: String,
+}
+
+///
+/// A located ffmpeg binary and what it was built with. Probing runs ffmpeg a few times, so
+/// build one of these once and share it between jobs.
+///
+/// ```no_run
+/// use clypperlib::download::installation::FFmpegInstallation;
+///
+/// let ffmpeg = FFmpegInstallation::discover().unwrap();
+/// println!("ffmpeg {} at {}", ffmpeg.version, ffmpeg.ffmpeg.display());
+/// assert!(ffmpeg.has_encoder("libx264"));
+/// ```
+///
+#[derive(Clone, Debug, Default)]
+pub struct FFmpegInstallation{
+    pub ffmpeg: PathBuf,
+    ///`None` if no ffprobe was found next to ffmpeg, in [`FFPROBE_ENV`] or on `PATH`
+    pub ffprobe: Option<PathBuf>,
+    ///The version string from `ffmpeg -version`, e.g. `6.1.1` or `n7.0` for git builds
+    pub version: String,
+    pub encoders: Vec<Encoder>,
+    pub filters: HashSet<String>,
+    ///Protocols ffmpeg can read inputs from, e.g. `https`
+    pub input_protocols: HashSet<String>,
+    pub output_protocols: HashSet<String>,
+}
+
+impl FFmpegInstallation{
+    ///
+    /// Finds ffmpeg through [`FFMPEG_ENV`], then `PATH`, and probes it
+    ///
+    pub fn discover() -> Result<Self, ClypperError>{
+        Self::locate(None)
+    }
+
+    ///
+    /// Probes the ffmpeg at `ffmpeg`, e.g. a path from the user's settings
+    ///
+    pub fn at(ffmpeg: impl Into<PathBuf>) -> Result<Self, ClypperError>{
+        Self::locate(Some(ffmpeg.into()))
+    }
+
+    fn locate(configured: Option<PathBuf>) -> Result<Self, ClypperError>{
+        let ffmpeg = match configured{
+            Some(path) if path.is_file() => path,
+            Some(path) => return Err(ClypperError::FFmpegMissing(path.display().to_string())),
+            None => find_program(FFMPEG_ENV, "ffmpeg")
+                .ok_or_else(|| ClypperError::FFmpegMissing(format!("${} or PATH", FFMPEG_ENV)))?,
+        };
+        //Prefer the ffprobe that came with this ffmpeg over whatever else is installed
+        let ffprobe = ffmpeg.parent()
+            .map(|dir| dir.join(executable_name("ffprobe")))
+            .filter(|path| path.is_file())
+            .or_else(|| find_program(FFPROBE_ENV, "ffprobe"));
+
+        let version = run(&ffmpeg, "-version")?;
+        let encoders = run(&ffmpeg, "-encoders")?;
+        let filters = run(&ffmpeg, "-filters")?;
+        let protocols = run(&ffmpeg, "-protocols")?;
+        let mut installation = Self::parse(ffmpeg, &version, &encoders, &filters, &protocols)?;
+        installation.ffprobe = ffprobe;
+        Ok(installation)
+    }
+
+    ///
+    /// Builds an installation from the output of `ffmpeg -version`, `-encoders`, `-filters` and `-protocols`
+    ///
+    pub fn parse(ffmpeg: PathBuf, version: &str, encoders: &str, filters: &str, protocols: &str) -> Result<Self, ClypperError>{
+        let version = version.lines()
+            .find_map(|line| line.strip_prefix("ffmpeg version "))
+            .and_then(|rest| rest.split_whitespace().next())
+            .ok_or_else(|| ClypperError::InvalidField("ffmpeg -version", version.lines().next().unwrap_or_default().to_string()))?
+            .to_string();
+        let (input_protocols, output_protocols) = parse_protocols(protocols);
+        Ok(Self{
+            ffmpeg,
+            ffprobe: None,
+            version,
+            encoders: parse_encoders(encoders),
+            filters: parse_filters(filters),
+            input_protocols,
+            output_protocols,
+        })
+    }
+
+    ///
+    /// The major version, `None` for builds that don't say, like `N-112345-g...` nightlies
+    ///
+    pub fn major_version(&self) -> Option<u32>{
+        let version = self.version.trim_start_matches('n');
+        let end = version.find(|c: char| !c.is_ascii_digit()).unwrap_or(version.len());
+        version[..end].parse().ok()
+    }
+
+    pub fn encoder(&self, name: &str) -> Option<&Encoder>{
+        self.encoders.iter().find(|encoder| encoder.name == name)
+    }
+
+    pub fn has_encoder(&self, name: &str) -> bool{
+        self.encoder(name).is_some()
+    }
+
+    pub fn has_filter(&self, name: &str) -> bool{
+        self.filters.contains(name)
+    }
+
+    pub fn can_read(&self, protocol: &str) -> bool{
+        self.input_protocols.contains(protocol)
+    }
+
+    ///
+    /// The first of `candidates` this ffmpeg has, failing with [`ClypperError::FFmpegUnsupported`] if it has none
+    ///
+    pub fn pick_encoder<'a>(&self, candidates: &[&'a str]) -> Result<&'a str, ClypperError>{
+        candidates.iter()
+            .copied()
+            .find(|candidate| self.has_encoder(candidate))
+            .ok_or_else(|| ClypperError::FFmpegUnsupported(format!("none of the encoders {} are available", candidates.join(", "))))
+    }
+}
+
+///
+/// The ffmpeg to run when no [`FFmpegInstallation`] was given: [`FFMPEG_ENV`] if set, otherwise
+/// whatever `ffmpeg` the OS finds
+///
+pub fn default_ffmpeg() -> PathBuf{
+    env::var_os(FFMPEG_ENV).map(PathBuf::from).unwrap_or_else(|| PathBuf::from("ffmpeg"))
+}
+
+///
+/// The ffprobe to run when no [`FFmpegInstallation`] was given: [`FFPROBE_ENV`] if set, otherwise
+/// whatever `ffprobe` the OS finds
+///
+pub fn default_ffprobe() -> PathBuf{
+    env::var_os(FFPROBE_ENV).map(PathBuf::from).unwrap_or_else(|| PathBuf::from("ffprobe"))
+}
+
+fn find_program(env_var: &str, name: &str) -> Option<PathBuf>{
+    if let Some(path) = env::var_os(env_var).map(PathBuf::from){
+        return path.is_file().then_some(path);
+    }
+    let name = executable_name(name);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+fn executable_name(name: &str) -> String{
+    if cfg!(windows) { format!("{}.exe", name) } else { name.to_string() }
+}
+
+fn run(program: &Path, arg: &str) -> Result<String, ClypperError>{
+    let output = Command::new(program)
+        .args(["-hide_banner", arg])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(ClypperError::FFmpegSpawn)?;
+    if !output.status.success(){
+        return Err(ClypperError::FFmpeg(format!("`{} {}` failed: {}", program.display(), arg, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+///
+/// Encoder lines look like ` V....D libx264    libx264 H.264 / AVC / MPEG-4 AVC (codec h264)`,
+/// after a legend that ends with a line of dashes
+///
+fn parse_encoders(output: &str) -> Vec<Encoder>{
+    output.lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut parts = line.trim().splitn(3, char::is_whitespace);
+            let kind = match parts.next()?.chars().next()?{
+                'V' => EncoderKind::Video,
+                'A' => EncoderKind::Audio,
+                'S' => EncoderKind::Subtitle,
+                _ => return None,
+            };
+            Some(Encoder{
+                name: parts.next()?.to_string(),
+                kind,
+                description: parts.next().unwrap_or_default().trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+///
+/// Filter lines look like ` TSC scale    V->V    Scale the input video size...`. The legend
+/// above them has no `->` column, which is how it's told apart.
+///
+fn parse_filters(output: &str) -> HashSet<String>{
+    output.lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().take(3).collect();
+            match parts.as_slice(){
+                [_, name, io] if io.contains("->") => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn parse_protocols(output: &str) -> (HashSet<String>, HashSet<String>){
+    let mut input = HashSet::new();
+    let mut output_protocols = HashSet::new();
+    let mut section = None;
+    for line in output.lines().map(str::trim){
+        match line{
+            "Input:" => section = Some(&mut input),
+            "Output:" => section = Some(&mut output_protocols),
+            "" => {},
+            protocol => if let Some(ref mut section) = section{
+                section.insert(protocol.to_string());
+            },
+        }
+    }
+    (input, output_protocols)
+}
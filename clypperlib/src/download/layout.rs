@@ -0,0 +1,235 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ClypperError, extract::metadata::ClipMetadata};
+
+use super::filter::{Filter, FilterGraph, ScaleFit, StreamType};
+
+///
+/// Part of the source frame, as fractions of its width and height so it holds for any resolution
+///
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SourceRect{
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl SourceRect{
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self{
+        Self{ x, y, width, height }
+    }
+
+    ///
+    /// The whole source frame
+    ///
+    pub fn full() -> Self{
+        Self::new(0.0, 0.0, 1.0, 1.0)
+    }
+}
+
+///
+/// Where a region goes in the output, in output pixels
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect{
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect{
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self{
+        Self{ x, y, width, height }
+    }
+}
+
+///
+/// A named piece of the source placed somewhere in the output. The source is scaled to cover
+/// the destination and cropped to it, so it never stretches.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Region{
+    pub name: String,
+    pub source: SourceRect,
+    pub destination: Rect,
+}
+
+///
+/// How to compose a clip's output from regions of its source frame, e.g. a streamer's facecam
+/// stacked on top of their gameplay. Regions are drawn in order, later ones on top.
+///
+/// ```
+/// use clypperlib::download::layout::{LayoutTemplate, SourceRect};
+///
+/// let layout = LayoutTemplate::stacked("webcam bottom right", SourceRect::new(0.75, 0.7, 0.25, 0.3), SourceRect::new(0.2, 0.0, 0.6, 1.0));
+/// assert_eq!(layout.regions[0].name, "facecam");
+/// ```
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayoutTemplate{
+    pub name: String,
+    ///Output width and height
+    #[serde(default = "default_size")]
+    pub size: (u32, u32),
+    ///What shows where no region covers, any color ffmpeg knows, e.g. `black` or `#1e1e2e`
+    #[serde(default = "default_background")]
+    pub background: String,
+    pub regions: Vec<Region>,
+}
+
+fn default_size() -> (u32, u32){
+    (1080, 1920)
+}
+
+fn default_background() -> String{
+    "black".to_string()
+}
+
+impl LayoutTemplate{
+    pub fn new(name: impl Into<String>, regions: Vec<Region>) -> Self{
+        Self{
+            name: name.into(),
+            size: default_size(),
+            background: default_background(),
+            regions,
+        }
+    }
+
+    ///
+    /// The usual Shorts layout: `facecam` across the top third of a 1080x1920 frame and
+    /// `gameplay` filling the rest below it
+    ///
+    pub fn stacked(name: impl Into<String>, facecam: SourceRect, gameplay: SourceRect) -> Self{
+        Self::new(name, vec![
+            Region{ name: "facecam".to_string(), source: facecam, destination: Rect::new(0, 0, 1080, 640) },
+            Region{ name: "gameplay".to_string(), source: gameplay, destination: Rect::new(0, 640, 1080, 1280) },
+        ])
+    }
+
+    pub fn region(&self, name: &str) -> Option<&Region>{
+        self.regions.iter().find(|region| region.name == name)
+    }
+
+    ///
+    /// Checks every region has a source inside the frame and a destination inside the output
+    ///
+    pub fn validate(&self) -> Result<(), ClypperError>{
+        let invalid = |message: String| Err(ClypperError::InvalidField("layout", format!("{}: {}", self.name, message)));
+        let (width, height) = self.size;
+        if self.regions.is_empty(){
+            return invalid("no regions".to_string());
+        }
+        if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0{
+            return invalid(format!("{}x{} isn't a size h264 can encode, both sides must be even", width, height));
+        }
+        for region in self.regions.iter(){
+            let SourceRect{ x, y, width: source_w, height: source_h } = region.source;
+            let inside = |start: f64, length: f64| start >= 0.0 && length > 0.0 && start + length <= 1.0 + f64::EPSILON;
+            if !inside(x, source_w) || !inside(y, source_h){
+                return invalid(format!("{}'s source isn't inside the frame", region.name));
+            }
+            let Rect{ x, y, width: dest_w, height: dest_h } = region.destination;
+            //Sizes come from user json, so a huge one mustn't overflow into looking inside
+            let outside = |start: u32, length: u32, max: u32| length == 0 || start.checked_add(length).is_none_or(|end| end > max);
+            if outside(x, dest_w, width) || outside(y, dest_h, height){
+                return invalid(format!("{} isn't inside the {}x{} output", region.name, width, height));
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// The filter graph from `input` to `output`, e.g. `0:v` to `v`
+    ///
+    pub fn filter_graph(&self, input: &str, output: &str) -> FilterGraph{
+        let (width, height) = self.size;
+        let count = self.regions.len();
+        let copies: Vec<String> = (0..=count).map(|i| format!("s{}", i)).collect();
+        let copies: Vec<&str> = copies.iter().map(String::as_str).collect();
+        let mut graph = FilterGraph::new();
+        //The background is drawn over a copy of the source rather than a color source, so the
+        //output keeps the source's frame rate and length
+        graph.chain(&[input], vec![Filter::Split{ kind: StreamType::Video, outputs: count as u32 + 1 }], &copies)
+            .chain(&["s0"], vec![Filter::scale(width, height), Filter::SetSar("1".to_string()), Filter::fill(self.background.as_str())], &["base0"]);
+        for (i, region) in self.regions.iter().enumerate(){
+            let SourceRect{ x, y, width: source_w, height: source_h } = region.source;
+            let Rect{ x: dest_x, y: dest_y, width: dest_w, height: dest_h } = region.destination;
+            let (copy, placed, base) = (format!("s{}", i + 1), format!("r{}", i), format!("base{}", i));
+            let into = if i + 1 == count { output.to_string() } else { format!("base{}", i + 1) };
+            graph.chain(&[copy.as_str()], vec![
+                Filter::crop(format!("iw*{}", source_w), format!("ih*{}", source_h), format!("iw*{}", x), format!("ih*{}", y)),
+                Filter::scale_fit(dest_w, dest_h, ScaleFit::Cover),
+                Filter::crop(dest_w, dest_h, "(iw-ow)/2", "(ih-oh)/2"),
+                Filter::SetSar("1".to_string()),
+            ], &[placed.as_str()])
+                .chain(&[base.as_str(), placed.as_str()], vec![Filter::overlay(dest_x, dest_y)], &[into.as_str()]);
+        }
+        graph
+    }
+}
+
+///
+/// Layout templates saved per channel, so each channel's clips come out composed the way that
+/// streamer's scene is set up. Stored as JSON keyed by channel id.
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LayoutStore{
+    channels: HashMap<String, LayoutTemplate>,
+}
+
+impl LayoutStore{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ClypperError>{
+        let json = std::fs::read_to_string(path)?;
+        Self::from_json(json.as_str())
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ClypperError>{
+        let store: Self = serde_json::from_str(json)
+            .map_err(|err| ClypperError::InvalidField("layouts", err.to_string()))?;
+        for template in store.channels.values(){
+            template.validate()?;
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ClypperError>{
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| ClypperError::InvalidField("layouts", err.to_string()))?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    ///
+    /// Saves `template` for `channel`, a channel id (or name, for sites without ids)
+    ///
+    pub fn set(&mut self, channel: impl Into<String>, template: LayoutTemplate) -> Result<(), ClypperError>{
+        template.validate()?;
+        self.channels.insert(channel.into(), template);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, channel: &str) -> Option<LayoutTemplate>{
+        self.channels.remove(channel)
+    }
+
+    pub fn get(&self, channel: &str) -> Option<&LayoutTemplate>{
+        self.channels.get(channel)
+    }
+
+    ///
+    /// The template saved for the clip's channel, looked up by id and then by name
+    ///
+    pub fn for_clip(&self, metadata: &ClipMetadata) -> Option<&LayoutTemplate>{
+        [metadata.channel_id.as_deref(), metadata.channel_name.as_deref()].into_iter()
+            .flatten()
+            .find_map(|channel| self.get(channel))
+    }
+}
@@ -0,0 +1,188 @@
+use serde::Deserialize;
+
+use crate::error::ClypperError;
+
+///
+/// The loudness [`super::ffmpeg::FFmpeg::normalize_loudness`] aims for, as EBU R128 measures it
+///
+/// ```
+/// use clypperlib::download::loudness::LoudnessTarget;
+///
+/// let podcast = LoudnessTarget::preset("podcast").unwrap();
+/// assert_eq!((podcast.integrated, podcast.true_peak), (-16.0, -1.5));
+/// ```
+///
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct LoudnessTarget{
+    ///Integrated loudness in LUFS, -70 to -5
+    pub integrated: f64,
+    ///Maximum true peak in dBTP, -9 to 0
+    pub true_peak: f64,
+    ///Loudness range in LU, 1 to 50. Louder passages are compressed if the clip's range is wider.
+    pub range: f64,
+}
+
+impl Default for LoudnessTarget{
+    fn default() -> Self{
+        Self::STREAMING
+    }
+}
+
+impl LoudnessTarget{
+    ///What YouTube and Spotify turn everything down to, so clips mastered louder gain nothing
+    pub const STREAMING: Self = Self{ integrated: -14.0, true_peak: -1.0, range: 11.0 };
+    ///Apple's recommendation for spoken word
+    pub const PODCAST: Self = Self{ integrated: -16.0, true_peak: -1.5, range: 11.0 };
+    ///EBU R128 itself, for TV
+    pub const BROADCAST: Self = Self{ integrated: -23.0, true_peak: -1.0, range: 20.0 };
+
+    pub fn new(integrated: f64, true_peak: f64, range: f64) -> Self{
+        Self{ integrated, true_peak, range }
+    }
+
+    ///
+    /// `streaming`, `podcast` or `broadcast`
+    ///
+    pub fn preset(name: &str) -> Option<Self>{
+        match name{
+            "streaming" => Some(Self::STREAMING),
+            "podcast" => Some(Self::PODCAST),
+            "broadcast" => Some(Self::BROADCAST),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Why ffmpeg would refuse the target, if it would
+    ///
+    pub(crate) fn check(&self) -> Result<(), String>{
+        if !(-70.0..=-5.0).contains(&self.integrated){
+            return Err(format!("integrated loudness {} LUFS is outside -70 to -5", self.integrated));
+        }
+        if !(-9.0..=0.0).contains(&self.true_peak){
+            return Err(format!("true peak {} dBTP is outside -9 to 0", self.true_peak));
+        }
+        if !(1.0..=50.0).contains(&self.range){
+            return Err(format!("loudness range {} LU is outside 1 to 50", self.range));
+        }
+        Ok(())
+    }
+}
+
+///
+/// How loud a stream is
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Loudness{
+    ///Integrated loudness in LUFS
+    pub integrated: f64,
+    ///True peak in dBTP
+    pub true_peak: f64,
+    ///Loudness range in LU
+    pub range: f64,
+    ///The gate below which audio doesn't count towards `integrated`, in LUFS
+    pub threshold: f64,
+}
+
+impl Loudness{
+    ///
+    /// Silence measures as -inf, which can't be normalised towards anything
+    ///
+    pub fn is_finite(&self) -> bool{
+        [self.integrated, self.true_peak, self.range, self.threshold].iter().all(|value| value.is_finite())
+    }
+}
+
+///
+/// What the `loudnorm` filter prints when it's done, the clip's loudness before and after
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoudnessReport{
+    pub input: Loudness,
+    pub output: Loudness,
+    ///The gain still missing to reach the target, fed back into the second pass
+    pub target_offset: f64,
+    ///The whole clip was scaled by one gain. Otherwise it was compressed, which happens when the
+    ///clip's loudness range is wider than the target's or its peaks would go over the true peak.
+    pub linear: bool,
+}
+
+impl LoudnessReport{
+    ///
+    /// Finds the last report in what ffmpeg printed on stderr, see [`LoudnessReportParser`]
+    ///
+    pub fn parse<'l>(lines: impl IntoIterator<Item = &'l str>) -> Option<Result<Self, ClypperError>>{
+        let mut parser = LoudnessReportParser::new();
+        lines.into_iter().filter_map(|line| parser.feed(line)).last()
+    }
+
+    fn from_json(json: &str) -> Result<Self, ClypperError>{
+        let raw: RawLoudnormReport = serde_json::from_str(json)
+            .map_err(|err| ClypperError::InvalidField("loudnorm", err.to_string()))?;
+        //Every value is a string, silence's are "-inf"
+        let number = |value: &str| value.trim().parse::<f64>()
+            .map_err(|_| ClypperError::InvalidField("loudnorm", format!("{} isn't a number", value)));
+        Ok(Self{
+            input: Loudness{
+                integrated: number(raw.input_i.as_str())?,
+                true_peak: number(raw.input_tp.as_str())?,
+                range: number(raw.input_lra.as_str())?,
+                threshold: number(raw.input_thresh.as_str())?,
+            },
+            output: Loudness{
+                integrated: number(raw.output_i.as_str())?,
+                true_peak: number(raw.output_tp.as_str())?,
+                range: number(raw.output_lra.as_str())?,
+                threshold: number(raw.output_thresh.as_str())?,
+            },
+            target_offset: number(raw.target_offset.as_str())?,
+            linear: raw.normalization_type.eq_ignore_ascii_case("linear"),
+        })
+    }
+}
+
+///
+/// Picks `loudnorm`'s report out of ffmpeg's stderr as it's read. The report is JSON over
+/// several lines, printed once the filter has seen the whole clip.
+///
+#[derive(Clone, Debug, Default)]
+pub struct LoudnessReportParser{
+    ///The report read so far, once its opening brace has been seen
+    json: Option<String>,
+}
+
+impl LoudnessReportParser{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    ///
+    /// Feeds one line of output, returning the report once its closing brace has been read
+    ///
+    pub fn feed(&mut self, line: &str) -> Option<Result<LoudnessReport, ClypperError>>{
+        let line = line.trim();
+        match self.json{
+            None if line == "{" => self.json = Some(line.to_string()),
+            None => {},
+            Some(ref mut json) => json.push_str(line),
+        }
+        if line != "}"{
+            return None;
+        }
+        self.json.take().map(|json| LoudnessReport::from_json(json.as_str()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawLoudnormReport{
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    output_i: String,
+    output_tp: String,
+    output_lra: String,
+    output_thresh: String,
+    normalization_type: String,
+    target_offset: String,
+}
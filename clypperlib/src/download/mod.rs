@@ -1,2 +1,24 @@
+//!
+//! Encodes extracted clips with ffmpeg. [`ffmpeg::FFmpeg`] builds the command and runs it as a
+//! background [`ffmpeg::FFmpegJob`], [`downloader::Downloader`] is a convenience wrapper with a
+//! terminal progress bar. [`cut`] decides whether the clip has to be re-encoded, [`filter`]
+//! builds the `-filter_complex` graph and [`verify`] checks the finished file with ffprobe.
+//! [`subtitles`] exports captions and styles the ones burned in, [`loudness`] evens out how
+//! loud clips are. With the `whisper` feature,
+//! `transcribe` makes captions from the clip's audio for videos that have none.
+//!
+pub mod cut;
+pub mod diagnostics;
 pub mod downloader;
 pub mod ffmpeg;
+pub mod filter;
+pub mod installation;
+pub mod layout;
+pub mod loudness;
+pub mod profile;
+pub mod progress;
+pub mod reframe;
+pub mod subtitles;
+#[cfg(feature = "whisper")]
+pub mod transcribe;
+pub mod verify;
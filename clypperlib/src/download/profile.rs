@@ -0,0 +1,300 @@
+use std::{fmt::Display, path::Path};
+
+use serde::Deserialize;
+
+use crate::error::ClypperError;
+
+///
+/// The file format written around the encoded streams
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container{
+    #[default]
+    Mp4,
+    Mkv,
+    Webm,
+    Mov,
+}
+
+impl Container{
+    ///
+    /// The container an output path asks for, `None` for extensions we don't write
+    ///
+    pub fn from_path(path: &Path) -> Option<Self>{
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str(){
+            "mp4" | "m4v" => Some(Self::Mp4),
+            "mkv" => Some(Self::Mkv),
+            "webm" => Some(Self::Webm),
+            "mov" => Some(Self::Mov),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str{
+        match self{
+            Self::Mp4 => "mp4",
+            Self::Mkv => "mkv",
+            Self::Webm => "webm",
+            Self::Mov => "mov",
+        }
+    }
+
+    ///
+    /// The muxer name passed to `-f`
+    ///
+    pub fn format_name(&self) -> &'static str{
+        match self{
+            Self::Mp4 => "mp4",
+            Self::Mkv => "matroska",
+            Self::Webm => "webm",
+            Self::Mov => "mov",
+        }
+    }
+
+    ///
+    /// Whether streams in `codec` (as ffprobe names it) can be copied into this container as they are
+    ///
+    pub fn can_hold(&self, codec: &str) -> bool{
+        match self{
+            Self::Mkv => true,
+            Self::Webm => matches!(codec, "vp8" | "vp9" | "av1" | "opus" | "vorbis"),
+            Self::Mp4 => matches!(codec, "h264" | "hevc" | "av1" | "vp9" | "aac" | "mp3" | "opus" | "flac"),
+            Self::Mov => matches!(codec, "h264" | "hevc" | "prores" | "aac" | "mp3" | "flac"),
+        }
+    }
+}
+
+impl Display for Container{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+///
+/// How the video encoder decides how many bits to spend
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoQuality{
+    ///Constant quality, lower is better. 18-28 is the useful range for x264.
+    Crf(u32),
+    ///Average bitrate in kbit/s
+    BitrateKbps(u32),
+}
+
+///
+/// Everything about how a clip is encoded, other than what's cut out of it. Use one of the
+/// [`EncodeProfile::builtins`] or load your own with [`EncodeProfiles::load`].
+///
+/// ```
+/// use clypperlib::download::profile::{Container, EncodeProfile};
+///
+/// let web = EncodeProfile::builtin("web").unwrap();
+/// assert_eq!(web.container, Container::Webm);
+/// ```
+///
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct EncodeProfile{
+    pub name: String,
+    ///The ffmpeg encoder, e.g. `libx264`
+    #[serde(default = "default_video_codec")]
+    pub video_codec: String,
+    pub quality: Option<VideoQuality>,
+    ///The encoder's speed/size trade-off, e.g. `veryfast` or `slow` for x264
+    pub preset: Option<String>,
+    ///e.g. `yuv420p`, which most players need
+    pub pixel_format: Option<String>,
+    #[serde(default = "default_audio_codec")]
+    pub audio_codec: String,
+    pub audio_bitrate_kbps: Option<u32>,
+    #[serde(default)]
+    pub container: Container,
+}
+
+fn default_video_codec() -> String{
+    "libx264".to_string()
+}
+
+fn default_audio_codec() -> String{
+    "aac".to_string()
+}
+
+impl Default for EncodeProfile{
+    fn default() -> Self{
+        Self::h264("default", 23, "medium", 160)
+    }
+}
+
+impl EncodeProfile{
+    fn h264(name: &str, crf: u32, preset: &str, audio_bitrate_kbps: u32) -> Self{
+        Self{
+            name: name.to_string(),
+            video_codec: default_video_codec(),
+            quality: Some(VideoQuality::Crf(crf)),
+            preset: Some(preset.to_string()),
+            pixel_format: Some("yuv420p".to_string()),
+            audio_codec: default_audio_codec(),
+            audio_bitrate_kbps: Some(audio_bitrate_kbps),
+            container: Container::Mp4,
+        }
+    }
+
+    ///
+    /// The profiles that come with clypper:
+    ///
+    /// - `default`: H.264/AAC in mp4, plays anywhere
+    /// - `high`: the same at a quality meant for editing or re-uploading
+    /// - `small`: the same, small enough for chat apps with upload limits
+    /// - `web`: VP9/Opus in webm
+    /// - `archive`: near-lossless H.264 with FLAC audio in mkv
+    ///
+    pub fn builtins() -> Vec<Self>{
+        vec![
+            Self::default(),
+            Self::h264("high", 18, "slow", 192),
+            Self::h264("small", 28, "veryfast", 96),
+            Self{
+                name: "web".to_string(),
+                video_codec: "libvpx-vp9".to_string(),
+                quality: Some(VideoQuality::Crf(32)),
+                preset: None,
+                pixel_format: Some("yuv420p".to_string()),
+                audio_codec: "libopus".to_string(),
+                audio_bitrate_kbps: Some(128),
+                container: Container::Webm,
+            },
+            Self{
+                audio_codec: "flac".to_string(),
+                audio_bitrate_kbps: None,
+                container: Container::Mkv,
+                ..Self::h264("archive", 14, "slow", 0)
+            },
+        ]
+    }
+
+    pub fn builtin(name: &str) -> Option<Self>{
+        Self::builtins().into_iter().find(|profile| profile.name == name)
+    }
+
+    ///
+    /// The encoder options for `video_codec`, which is the profile's own encoder or a fallback
+    /// for it. A fallback gets no rate control or preset, those are specific to an encoder.
+    ///
+    pub fn video_args(&self, video_codec: &str) -> Vec<String>{
+        let mut args = vec!["-c:v".to_string(), video_codec.to_string()];
+        if video_codec == self.video_codec{
+            match self.quality{
+                Some(VideoQuality::Crf(crf)) => {
+                    args.extend(["-crf".to_string(), crf.to_string()]);
+                    //libvpx only does constant quality without a bitrate cap
+                    if video_codec.starts_with("libvpx"){
+                        args.extend(["-b:v".to_string(), "0".to_string()]);
+                    }
+                },
+                Some(VideoQuality::BitrateKbps(bitrate)) => args.extend(["-b:v".to_string(), format!("{}k", bitrate)]),
+                None => {},
+            }
+            if let Some(ref preset) = self.preset{
+                args.extend(["-preset".to_string(), preset.clone()]);
+            }
+        }
+        if let Some(ref pixel_format) = self.pixel_format{
+            args.extend(["-pix_fmt".to_string(), pixel_format.clone()]);
+        }
+        args
+    }
+
+    pub fn audio_args(&self, audio_codec: &str) -> Vec<String>{
+        let mut args = vec!["-c:a".to_string(), audio_codec.to_string()];
+        if let Some(bitrate) = self.audio_bitrate_kbps{
+            args.extend(["-b:a".to_string(), format!("{}k", bitrate)]);
+        }
+        args
+    }
+
+    ///
+    /// The muxer options for the profile's container
+    ///
+    pub fn container_args(&self) -> Vec<String>{
+        let mut args = vec!["-f".to_string(), self.container.format_name().to_string()];
+        if matches!(self.container, Container::Mp4 | Container::Mov){
+            //Puts the index up front so players can start before the whole file is read
+            args.extend(["-movflags".to_string(), "+faststart".to_string()]);
+        }
+        args
+    }
+}
+
+///
+/// The codec an encoder produces, as ffprobe names it, for the encoders clypper knows about
+///
+pub fn codec_name(encoder: &str) -> Option<&'static str>{
+    let codec = match encoder{
+        "libx264" | "libopenh264" => "h264",
+        "libx265" => "hevc",
+        "libvpx" => "vp8",
+        "libvpx-vp9" => "vp9",
+        "libaom-av1" | "libsvtav1" | "librav1e" => "av1",
+        "prores" | "prores_ks" | "prores_aw" => "prores",
+        "aac" | "libfdk_aac" => "aac",
+        "libopus" | "opus" => "opus",
+        "libvorbis" | "vorbis" => "vorbis",
+        "libmp3lame" => "mp3",
+        "flac" => "flac",
+        //Hardware encoders are named after their codec, e.g. h264_nvenc or hevc_videotoolbox
+        hardware => ["h264", "hevc", "av1"].into_iter().find(|codec| hardware.starts_with(&format!("{}_", codec)))?,
+    };
+    Some(codec)
+}
+
+///
+/// The built-in profiles plus any the user defined. User profiles replace built-ins of the same name.
+///
+#[derive(Clone, Debug)]
+pub struct EncodeProfiles{
+    profiles: Vec<EncodeProfile>,
+}
+
+impl Default for EncodeProfiles{
+    fn default() -> Self{
+        Self{ profiles: EncodeProfile::builtins() }
+    }
+}
+
+impl EncodeProfiles{
+    ///
+    /// The built-ins plus the profiles in the JSON file at `path`, a list of [`EncodeProfile`]s like
+    /// `[{"name": "discord", "quality": {"bitrate_kbps": 2000}, "preset": "fast"}]`
+    ///
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ClypperError>{
+        let json = std::fs::read_to_string(path)?;
+        Self::from_json(json.as_str())
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ClypperError>{
+        let user: Vec<EncodeProfile> = serde_json::from_str(json)
+            .map_err(|err| ClypperError::InvalidField("profiles", err.to_string()))?;
+        let mut profiles = Self::default();
+        for profile in user{
+            profiles.insert(profile);
+        }
+        Ok(profiles)
+    }
+
+    pub fn insert(&mut self, profile: EncodeProfile){
+        match self.profiles.iter_mut().find(|existing| existing.name == profile.name){
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EncodeProfile>{
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &EncodeProfile>{
+        self.profiles.iter()
+    }
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+///
+/// One block of ffmpeg's `-progress` output, with how far along the clip it is
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProgressReport{
+    ///Frames encoded so far
+    pub frame: Option<u64>,
+    ///Frames encoded per second of wall time
+    pub fps: Option<f64>,
+    ///Output bitrate in kbit/s
+    pub bitrate_kbps: Option<f64>,
+    ///Bytes written so far
+    pub total_size: Option<u64>,
+    ///How much of the output has been written, in milliseconds of media
+    pub out_time_ms: u64,
+    ///Media time encoded per second of wall time, e.g. `2.0` for twice realtime
+    pub speed: Option<f64>,
+    ///Whether this is ffmpeg's final report
+    pub end: bool,
+    ///0 to 100, `None` if the length of the clip isn't known
+    pub percent: Option<f64>,
+    ///Estimated time left, from the remaining media time and the current speed
+    pub eta: Option<Duration>,
+}
+
+///
+/// Reads ffmpeg's `-progress` output line by line. ffmpeg writes a block of `key=value` lines
+/// ending with `progress=continue` or `progress=end`, anything else is ignored so the parser
+/// can be fed stderr with log lines mixed in.
+///
+/// ```
+/// use clypperlib::download::progress::ProgressParser;
+///
+/// let mut parser = ProgressParser::new(10_000);
+/// assert!(parser.feed("out_time_us=2500000").is_none());
+/// let report = parser.feed("progress=continue").unwrap();
+/// assert_eq!(report.percent, Some(25.0));
+/// ```
+///
+#[derive(Clone, Debug, Default)]
+pub struct ProgressParser{
+    duration_ms: u64,
+    offset_ms: u64,
+    partial: bool,
+    current: ProgressReport,
+}
+
+impl ProgressParser{
+    ///
+    /// `duration_ms` is the length of the output, used to work out percent and ETA. Pass 0 if unknown.
+    ///
+    pub fn new(duration_ms: u64) -> Self{
+        Self{
+            duration_ms,
+            offset_ms: 0,
+            partial: false,
+            current: ProgressReport::default(),
+        }
+    }
+
+    ///
+    /// For jobs that run ffmpeg more than once, where this run writes the part of the output
+    /// starting `offset_ms` in. Reported times are shifted by the offset, and the run finishing
+    /// isn't reported as the end of the job.
+    ///
+    pub fn partial(mut self, offset_ms: u64) -> Self{
+        self.offset_ms = offset_ms;
+        self.partial = true;
+        self
+    }
+
+    ///
+    /// Whether `line` is part of a `-progress` block rather than one of ffmpeg's log messages
+    ///
+    pub fn is_progress_line(line: &str) -> bool{
+        let Some((key, _)) = line.trim().split_once('=') else{
+            return false;
+        };
+        matches!(key, "frame" | "fps" | "bitrate" | "total_size" | "out_time_us" | "out_time_ms" | "out_time"
+            | "dup_frames" | "drop_frames" | "speed" | "progress")
+            || (key.starts_with("stream_") && key.ends_with("_q"))
+    }
+
+    ///
+    /// Feeds one line of output, returning a report once a whole block has been read
+    ///
+    pub fn feed(&mut self, line: &str) -> Option<ProgressReport>{
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key{
+            "frame" => self.current.frame = value.parse().ok(),
+            "fps" => self.current.fps = value.parse().ok(),
+            "bitrate" => self.current.bitrate_kbps = value.trim_end_matches("kbits/s").parse().ok(),
+            "total_size" => self.current.total_size = value.parse().ok(),
+            "out_time_us" => {
+                if let Ok(out_time_us) = value.parse::<u64>(){
+                    self.current.out_time_ms = self.offset_ms + out_time_us / 1000;
+                }
+            },
+            "speed" => self.current.speed = value.trim_end_matches('x').parse().ok(),
+            "progress" => return Some(self.finish_block(value == "end")),
+            _ => {}
+        }
+        None
+    }
+
+    fn finish_block(&mut self, end: bool) -> ProgressReport{
+        let mut report = self.current.clone();
+        let end = end && !self.partial;
+        report.end = end;
+        if self.duration_ms > 0{
+            let done_ms = if end { self.duration_ms } else { report.out_time_ms.min(self.duration_ms) };
+            report.percent = Some(done_ms as f64 * 100.0 / self.duration_ms as f64);
+            report.eta = match report.speed{
+                _ if end => Some(Duration::ZERO),
+                Some(speed) if speed > 0.0 => {
+                    let remaining_ms = (self.duration_ms - done_ms) as f64 / speed;
+                    Some(Duration::from_millis(remaining_ms as u64))
+                },
+                _ => None,
+            };
+        }
+        report
+    }
+}
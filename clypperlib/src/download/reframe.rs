@@ -0,0 +1,170 @@
+use serde::Deserialize;
+
+use super::filter::{Filter, FilterGraph, ScaleFit, StreamType};
+
+///
+/// The shape of a reframed output
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AspectRatio{
+    ///9:16, for Shorts, TikTok and Reels
+    #[default]
+    Vertical,
+    ///1:1
+    Square,
+    ///4:5, the tallest Instagram allows in the feed
+    Portrait,
+}
+
+impl AspectRatio{
+    ///
+    /// Width and height, in that order
+    ///
+    pub fn ratio(&self) -> (u32, u32){
+        match self{
+            Self::Vertical => (9, 16),
+            Self::Square => (1, 1),
+            Self::Portrait => (4, 5),
+        }
+    }
+
+    ///
+    /// The output size used unless one is given, 1080 pixels wide
+    ///
+    pub fn default_size(&self) -> (u32, u32){
+        let (width, height) = self.ratio();
+        (1080, 1080 * height / width)
+    }
+}
+
+///
+/// Where the crop window sits at `time_ms` into the clip, see [`ReframeMode::Pan`]
+///
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct PanKeyframe{
+    pub time_ms: u64,
+    pub offset: f64,
+}
+
+///
+/// How the source frame is fitted into the new shape. Offsets go from 0.0, the crop window
+/// against the left edge of the source, to 1.0 against the right edge.
+///
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReframeMode{
+    ///Crop the middle of the frame
+    #[default]
+    Center,
+    ///Crop at a fixed horizontal offset, e.g. to keep a facecam in shot
+    Offset(f64),
+    ///Crop at an offset that moves between keyframes, linearly. Before the first keyframe and
+    ///after the last the window stays put.
+    Pan(Vec<PanKeyframe>),
+    ///Keep the whole frame, scaled to fit, over a blurred and zoomed copy of itself
+    BlurPad,
+}
+
+///
+/// Turns the source frame into a vertical, square or portrait one
+///
+/// ```
+/// use clypperlib::download::reframe::{AspectRatio, Reframe, ReframeMode};
+///
+/// let reframe = Reframe::new(AspectRatio::Vertical, ReframeMode::Offset(0.25));
+/// assert_eq!(reframe.size(), (1080, 1920));
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Reframe{
+    pub aspect: AspectRatio,
+    #[serde(default)]
+    pub mode: ReframeMode,
+    ///Output width and height, [`AspectRatio::default_size`] if not given
+    #[serde(default)]
+    pub size: Option<(u32, u32)>,
+}
+
+impl Reframe{
+    pub fn new(aspect: AspectRatio, mode: ReframeMode) -> Self{
+        Self{
+            aspect,
+            mode,
+            size: None,
+        }
+    }
+
+    ///
+    /// Scales the output to `width`x`height` instead of 1080 pixels wide. Should match the aspect ratio.
+    ///
+    pub fn with_size(mut self, width: u32, height: u32) -> Self{
+        self.size = Some((width, height));
+        self
+    }
+
+    pub fn size(&self) -> (u32, u32){
+        self.size.unwrap_or_else(|| self.aspect.default_size())
+    }
+
+    ///
+    /// The filter graph from `input` to `output`, e.g. `0:v` to `v`
+    ///
+    pub fn filter_graph(&self, input: &str, output: &str) -> FilterGraph{
+        let (width, height) = self.size();
+        let (ratio_w, ratio_h) = self.aspect.ratio();
+        let mut graph = FilterGraph::new();
+        let x = match self.mode{
+            ReframeMode::Center => "(iw-ow)/2".to_string(),
+            ReframeMode::Offset(offset) => format!("(iw-ow)*{}", offset.clamp(0.0, 1.0)),
+            ReframeMode::Pan(ref keyframes) => format!("(iw-ow)*({})", pan_expression(keyframes)),
+            ReframeMode::BlurPad => {
+                graph.chain(&[input], vec![Filter::Split{ kind: StreamType::Video, outputs: 2 }], &["bg", "fg"])
+                    .chain(&["bg"], vec![
+                        Filter::scale_fit(width, height, ScaleFit::Cover),
+                        Filter::crop(width, height, "(iw-ow)/2", "(ih-oh)/2"),
+                        Filter::BoxBlur{ radius: 20, power: 5 },
+                    ], &["blurred"])
+                    .chain(&["fg"], vec![Filter::scale_fit(width, height, ScaleFit::Contain)], &["fitted"])
+                    .chain(&["blurred", "fitted"], vec![Filter::overlay("(W-w)/2", "(H-h)/2"), Filter::SetSar("1".to_string())], &[output]);
+                return graph;
+            },
+        };
+        //The largest window of the new shape that fits in the source, whichever way round the source is
+        let crop = Filter::crop(
+            format!("min(iw,ih*{}/{})", ratio_w, ratio_h),
+            format!("min(ih,iw*{}/{})", ratio_h, ratio_w),
+            x,
+            "(ih-oh)/2",
+        );
+        graph.chain(&[input], vec![crop, Filter::scale(width, height), Filter::SetSar("1".to_string())], &[output]);
+        graph
+    }
+}
+
+///
+/// An expression in `t`, the output time in seconds, for the offset between `keyframes`
+///
+fn pan_expression(keyframes: &[PanKeyframe]) -> String{
+    let mut keyframes = keyframes.to_vec();
+    keyframes.sort_by_key(|keyframe| keyframe.time_ms);
+    let offset = |keyframe: &PanKeyframe| keyframe.offset.clamp(0.0, 1.0);
+    let Some(last) = keyframes.last() else{
+        return "0.5".to_string();
+    };
+    //Built from the last keyframe backwards, each segment wrapping the ones after it
+    let mut expression = offset(last).to_string();
+    for pair in keyframes.windows(2).rev(){
+        let (from, to) = (&pair[0], &pair[1]);
+        let (from_s, to_s) = (from.time_ms as f64 / 1000.0, to.time_ms as f64 / 1000.0);
+        if to_s <= from_s{
+            continue;
+        }
+        expression = format!(
+            "if(lt(t,{}),{}+({})*(t-{})/{},{})",
+            to_s, offset(from), offset(to) - offset(from), from_s, to_s - from_s, expression,
+        );
+    }
+    let first = &keyframes[0];
+    format!("if(lt(t,{}),{},{})", first.time_ms as f64 / 1000.0, offset(first), expression)
+}
@@ -0,0 +1,355 @@
+use std::{fmt::Write, path::Path, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{error::ClypperError, extract::captions::{CaptionWord, Captions, Cue}};
+
+///
+/// The frame ASS subtitles are laid out on when the output size isn't known. libass scales the
+/// script to the video, so this only has to have the shape of most sources.
+///
+pub const DEFAULT_PLAY_RES: (u32, u32) = (1920, 1080);
+
+///
+/// The subtitle formats captions can be exported to
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubtitleFormat{
+    #[default]
+    Srt,
+    Vtt,
+    ///Advanced SubStation Alpha, the only one that carries a [`CaptionStyle`]
+    Ass,
+}
+
+impl SubtitleFormat{
+    ///
+    /// The format a path's extension asks for, `None` for anything else
+    ///
+    pub fn from_path(path: &Path) -> Option<Self>{
+        let extension = path.extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str(){
+            "srt" => Some(Self::Srt),
+            "vtt" => Some(Self::Vtt),
+            "ass" | "ssa" => Some(Self::Ass),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str{
+        match self{
+            Self::Srt => "srt",
+            Self::Vtt => "vtt",
+            Self::Ass => "ass",
+        }
+    }
+
+    ///
+    /// Writes `captions` out in this format. `style` and `play_res`, the output's size, only
+    /// matter for ASS.
+    ///
+    pub fn render(&self, captions: &Captions, style: &CaptionStyle, play_res: (u32, u32)) -> String{
+        match self{
+            Self::Srt => to_srt(captions),
+            Self::Vtt => to_vtt(captions),
+            Self::Ass => to_ass(captions, style, play_res),
+        }
+    }
+}
+
+///
+/// An RGB color with an alpha, 255 being opaque. Parses from `#rrggbb` or `#rrggbbaa`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color{
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+impl Color{
+    pub const WHITE: Self = Self::rgb(255, 255, 255);
+    pub const BLACK: Self = Self::rgb(0, 0, 0);
+
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self{
+        Self::rgba(red, green, blue, 255)
+    }
+
+    pub const fn rgba(red: u8, green: u8, blue: u8, alpha: u8) -> Self{
+        Self{ red, green, blue, alpha }
+    }
+
+    ///
+    /// As ASS writes colors, `&HAABBGGRR` with the alpha inverted so 00 is opaque
+    ///
+    pub fn ass(&self) -> String{
+        format!("&H{:02X}{:02X}{:02X}{:02X}", 255 - self.alpha, self.blue, self.green, self.red)
+    }
+
+    ///
+    /// As ASS override tags write colors, `&HBBGGRR&` without the alpha
+    ///
+    fn ass_override(&self) -> String{
+        format!("&H{:02X}{:02X}{:02X}&", self.blue, self.green, self.red)
+    }
+}
+
+impl FromStr for Color{
+    type Err = ClypperError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err>{
+        let invalid = || ClypperError::InvalidField("color", value.to_string());
+        let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii(){
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+        Ok(Self::rgba(channel(0)?, channel(2)?, channel(4)?, alpha))
+    }
+}
+
+impl TryFrom<String> for Color{
+    type Error = ClypperError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error>{
+        value.parse()
+    }
+}
+
+///
+/// Where captions sit on the frame
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptionPosition{
+    #[default]
+    Bottom,
+    Middle,
+    Top,
+}
+
+impl CaptionPosition{
+    ///
+    /// ASS's numpad style alignment, centred horizontally
+    ///
+    fn alignment(&self) -> u32{
+        match self{
+            Self::Bottom => 2,
+            Self::Middle => 5,
+            Self::Top => 8,
+        }
+    }
+}
+
+///
+/// How burned in captions look. Sizes are in pixels of the output frame.
+///
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CaptionStyle{
+    ///Any font fontconfig can find, e.g. `Arial` or `Montserrat Black`
+    pub font: String,
+    pub font_size: u32,
+    pub bold: bool,
+    pub color: Color,
+    pub outline_color: Color,
+    pub outline: u32,
+    pub shadow: u32,
+    ///Draws a box of this color behind the text instead of an outline
+    pub background: Option<Color>,
+    pub position: CaptionPosition,
+    ///Distance from the top or bottom edge
+    pub margin_vertical: u32,
+    ///Distance from the left and right edges, text wraps before it
+    pub margin_horizontal: u32,
+    ///Shows a few words at a time and highlights each as it's spoken instead of showing whole cues
+    pub karaoke: Option<Karaoke>,
+}
+
+impl Default for CaptionStyle{
+    fn default() -> Self{
+        Self{
+            font: "Arial".to_string(),
+            font_size: 64,
+            bold: true,
+            color: Color::WHITE,
+            outline_color: Color::BLACK,
+            outline: 4,
+            shadow: 0,
+            background: None,
+            position: CaptionPosition::Bottom,
+            margin_vertical: 120,
+            margin_horizontal: 60,
+            karaoke: None,
+        }
+    }
+}
+
+///
+/// Word by word captions, the way Shorts usually have them. Uses each word's timing where the
+/// track has it, and spreads a cue's time over its words by their length where it doesn't.
+///
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Karaoke{
+    ///The color of the word being spoken
+    pub highlight_color: Color,
+    ///How much bigger the word being spoken is, in percent. 100 leaves it as is.
+    pub highlight_scale: u32,
+    pub max_words_per_line: usize,
+    pub uppercase: bool,
+}
+
+impl Default for Karaoke{
+    fn default() -> Self{
+        Self{
+            highlight_color: Color::rgb(255, 221, 0),
+            highlight_scale: 100,
+            max_words_per_line: 3,
+            uppercase: false,
+        }
+    }
+}
+
+impl Karaoke{
+    ///
+    /// The dialogues for `cue`: one per word, each showing the word's line with it highlighted
+    ///
+    fn dialogues(&self, ass: &mut String, cue: &Cue){
+        let words = if cue.words.is_empty() { spread_words(cue) } else { cue.words.clone() };
+        for line in words.chunks(self.max_words_per_line.max(1)){
+            for (i, word) in line.iter().enumerate(){
+                //Each word stays highlighted until the next one starts, so the line doesn't flicker between them
+                let end_ms = line.get(i + 1).map_or(word.end_ms, |next| next.start_ms);
+                if end_ms <= word.start_ms{
+                    continue;
+                }
+                let text: Vec<String> = line.iter().enumerate()
+                    .map(|(j, other)| {
+                        let text = if self.uppercase { ass_text(other.text.to_uppercase().as_str()) } else { ass_text(other.text.as_str()) };
+                        if i != j{
+                            return text;
+                        }
+                        let scale = match self.highlight_scale{
+                            100 => String::new(),
+                            scale => format!("\\fscx{}\\fscy{}", scale, scale),
+                        };
+                        format!("{{\\c{}{}}}{}{{\\r}}", self.highlight_color.ass_override(), scale, text)
+                    })
+                    .collect();
+                ass_dialogue(ass, word.start_ms, end_ms, text.join(" ").as_str());
+            }
+        }
+    }
+}
+
+///
+/// A cue's words timed by how much of its text each one is, for tracks that don't time words
+///
+fn spread_words(cue: &Cue) -> Vec<CaptionWord>{
+    let words: Vec<&str> = cue.text.split_whitespace().collect();
+    let total: u64 = words.iter().map(|word| word.chars().count() as u64).sum();
+    let duration_ms = cue.end_ms.saturating_sub(cue.start_ms);
+    let mut start_ms = cue.start_ms;
+    let mut counted = 0;
+    words.into_iter()
+        .map(|word| {
+            counted += word.chars().count() as u64;
+            let end_ms = cue.start_ms + duration_ms * counted / total.max(1);
+            let timed = CaptionWord{ start_ms, end_ms, text: word.to_string() };
+            start_ms = end_ms;
+            timed
+        })
+        .collect()
+}
+
+///
+/// SubRip, the most widely understood format
+///
+pub fn to_srt(captions: &Captions) -> String{
+    let mut srt = String::new();
+    for (i, cue) in captions.cues.iter().enumerate(){
+        let _ = write!(srt, "{}\n{} --> {}\n{}\n\n", i + 1, timestamp(cue.start_ms, ','), timestamp(cue.end_ms, ','), cue.text);
+    }
+    srt
+}
+
+///
+/// WebVTT, for players in browsers
+///
+pub fn to_vtt(captions: &Captions) -> String{
+    let mut vtt = "WEBVTT\n\n".to_string();
+    for cue in captions.cues.iter(){
+        //WebVTT reads `<` and `&` as markup
+        let text = cue.text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+        let _ = write!(vtt, "{} --> {}\n{}\n\n", timestamp(cue.start_ms, '.'), timestamp(cue.end_ms, '.'), text);
+    }
+    vtt
+}
+
+///
+/// Advanced SubStation Alpha with `style` applied to every cue, laid out on a `play_res` frame.
+/// With [`CaptionStyle::karaoke`] set the cues are broken into highlighted words.
+///
+pub fn to_ass(captions: &Captions, style: &CaptionStyle, play_res: (u32, u32)) -> String{
+    let mut ass = ass_header(style, play_res);
+    for cue in captions.cues.iter(){
+        match style.karaoke{
+            Some(ref karaoke) => karaoke.dialogues(&mut ass, cue),
+            None => ass_dialogue(&mut ass, cue.start_ms, cue.end_ms, ass_text(cue.text.as_str()).as_str()),
+        }
+    }
+    ass
+}
+
+///
+/// The script info and `Default` style every ASS file starts with
+///
+fn ass_header(style: &CaptionStyle, play_res: (u32, u32)) -> String{
+    let (border_style, back_color) = match style.background{
+        Some(background) => (3, background),
+        None => (1, Color::rgba(0, 0, 0, 128)),
+    };
+    let mut ass = String::new();
+    let _ = write!(ass, "[Script Info]\nScriptType: v4.00+\nPlayResX: {}\nPlayResY: {}\nWrapStyle: 0\nScaledBorderAndShadow: yes\n\n", play_res.0, play_res.1);
+    ass.push_str("[V4+ Styles]\nFormat: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n");
+    let _ = write!(
+        ass,
+        "Style: Default,{},{},{},{},{},{},{},0,0,0,100,100,0,0,{},{},{},{},{},{},{},1\n\n",
+        style.font.replace(',', " "), style.font_size, style.color.ass(), style.color.ass(), style.outline_color.ass(), back_color.ass(),
+        if style.bold { -1 } else { 0 }, border_style, style.outline, style.shadow, style.position.alignment(),
+        style.margin_horizontal, style.margin_horizontal, style.margin_vertical,
+    );
+    ass.push_str("[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n");
+    ass
+}
+
+fn ass_dialogue(ass: &mut String, start_ms: u64, end_ms: u64, text: &str){
+    let _ = writeln!(ass, "Dialogue: 0,{},{},Default,,0,0,0,,{}", ass_timestamp(start_ms), ass_timestamp(end_ms), text);
+}
+
+///
+/// Cue text as ASS shows it. ASS has no way to escape the braces override tags go in, so they
+/// become parentheses.
+///
+fn ass_text(text: &str) -> String{
+    text.replace('{', "(").replace('}', ")").replace('\n', "\\N")
+}
+
+///
+/// `hh:mm:ss,mmm` for SRT, `hh:mm:ss.mmm` for WebVTT
+///
+fn timestamp(ms: u64, separator: char) -> String{
+    format!("{:02}:{:02}:{:02}{}{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, separator, ms % 1000)
+}
+
+///
+/// ASS counts in centiseconds, `h:mm:ss.cc`
+///
+fn ass_timestamp(ms: u64) -> String{
+    format!("{}:{:02}:{:02}.{:02}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000 / 10)
+}
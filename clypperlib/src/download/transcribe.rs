@@ -0,0 +1,151 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState};
+
+use crate::{error::ClypperError, extract::captions::{CaptionWord, Captions}};
+
+///The sample rate whisper models are trained on
+const SAMPLE_RATE: u32 = 16_000;
+///How many words [`Transcriber::transcribe`] puts in a cue
+const DEFAULT_WORDS_PER_CUE: usize = 8;
+
+///
+/// Turns speech into word timed [`Captions`] with a whisper model on disk, e.g. `ggml-base.en.bin`
+/// from whisper.cpp. Runs on the CPU and never touches the network.
+///
+/// ```no_run
+/// use clypperlib::download::transcribe::Transcriber;
+///
+/// let transcriber = Transcriber::load("models/ggml-base.en.bin").unwrap().language("en");
+/// let samples = vec![0.0; 16_000];
+/// let captions = transcriber.transcribe(&samples).unwrap();
+/// ```
+///
+pub struct Transcriber{
+    context: WhisperContext,
+    language: Option<String>,
+    threads: usize,
+    words_per_cue: usize,
+}
+
+impl Transcriber{
+    pub fn load(model: impl AsRef<Path>) -> Result<Self, ClypperError>{
+        let model = model.as_ref();
+        let path = model.to_str().ok_or_else(|| ClypperError::Transcription(format!("{} isn't a valid path", model.display())))?;
+        let context = WhisperContext::new_with_params(path, WhisperContextParameters::default())
+            .map_err(|err| ClypperError::Transcription(format!("can't load {}: {}", model.display(), err)))?;
+        Ok(Self{
+            context,
+            language: None,
+            threads: std::thread::available_parallelism().map_or(4, |threads| threads.get()),
+            words_per_cue: DEFAULT_WORDS_PER_CUE,
+        })
+    }
+
+    ///
+    /// The language spoken, e.g. `en`. Detected from the audio unless given, which English only
+    /// models can't do.
+    ///
+    pub fn language(mut self, language: impl Into<String>) -> Self{
+        self.language = Some(language.into());
+        self
+    }
+
+    ///
+    /// How many CPU threads to run the model on, all of them by default
+    ///
+    pub fn threads(mut self, threads: usize) -> Self{
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn words_per_cue(mut self, words: usize) -> Self{
+        self.words_per_cue = words.max(1);
+        self
+    }
+
+    ///
+    /// Transcribes 16kHz mono `samples`, see [`extract_audio`]. Times count from the first sample.
+    ///
+    pub fn transcribe(&self, samples: &[f32]) -> Result<Captions, ClypperError>{
+        let failed = |err: whisper_rs::WhisperError| ClypperError::Transcription(err.to_string());
+        let mut state = self.context.create_state().map_err(failed)?;
+        let mut params = FullParams::new(SamplingStrategy::Greedy{ best_of: 1 });
+        params.set_language(Some(self.language.as_deref().unwrap_or("auto")));
+        params.set_n_threads(self.threads as i32);
+        params.set_translate(false);
+        //One word per segment, so every word gets its own timestamps
+        params.set_token_timestamps(true);
+        params.set_max_len(1);
+        params.set_split_on_word(true);
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+        state.full(params, samples).map_err(failed)?;
+        let words = words(&state).map_err(failed)?;
+        Ok(Captions::from_words(words, self.words_per_cue))
+    }
+
+    ///
+    /// Transcribes `input`'s audio from `start_ms` to `end_ms`, timed from `start_ms`
+    ///
+    pub fn transcribe_input(&self, ffmpeg: &Path, input: &str, start_ms: u64, end_ms: u64) -> Result<Captions, ClypperError>{
+        let samples = extract_audio(ffmpeg, input, start_ms, end_ms)?;
+        self.transcribe(&samples)
+    }
+}
+
+///
+/// The words whisper found, skipping its non-speech markers like `[BLANK_AUDIO]`
+///
+fn words(state: &WhisperState) -> Result<Vec<CaptionWord>, whisper_rs::WhisperError>{
+    let mut words = vec![];
+    for i in 0..state.full_n_segments()?{
+        let text = state.full_get_segment_text(i)?;
+        let (start, end) = (state.full_get_segment_t0(i)?, state.full_get_segment_t1(i)?);
+        words.extend(segment_word(text.as_str(), start, end));
+    }
+    Ok(words)
+}
+
+///
+/// The word in one of whisper's segments, `None` for silence and markers. Segment times are in
+/// centiseconds and can come back negative or out of order.
+///
+pub(crate) fn segment_word(text: &str, start: i64, end: i64) -> Option<CaptionWord>{
+    let text = text.trim();
+    if text.is_empty() || (text.starts_with('[') && text.ends_with(']')){
+        return None;
+    }
+    Some(CaptionWord{
+        start_ms: start.max(0) as u64 * 10,
+        end_ms: end.max(start).max(0) as u64 * 10,
+        text: text.to_string(),
+    })
+}
+
+///
+/// Decodes `input`'s first audio stream from `start_ms` to `end_ms` with ffmpeg into the 16kHz
+/// mono samples whisper wants
+///
+pub fn extract_audio(ffmpeg: &Path, input: &str, start_ms: u64, end_ms: u64) -> Result<Vec<f32>, ClypperError>{
+    //Reads stdout and stderr together, a chatty ffmpeg would block on a full stderr pipe otherwise
+    let output = Command::new(ffmpeg)
+        .args(["-hide_banner", "-loglevel", "error", "-nostdin"])
+        .args(["-ss", format!("{}ms", start_ms).as_str(), "-to", format!("{}ms", end_ms).as_str(), "-i", input])
+        .args(["-map", "0:a:0", "-ac", "1", "-ar", SAMPLE_RATE.to_string().as_str(), "-f", "f32le", "pipe:1"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| match err.kind(){
+            std::io::ErrorKind::NotFound => ClypperError::FFmpegMissing(ffmpeg.display().to_string()),
+            _ => ClypperError::FFmpegSpawn(err),
+        })?;
+    if !output.status.success(){
+        return Err(ClypperError::FFmpeg(format!("can't decode audio for transcription: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(output.stdout.chunks_exact(4).map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])).collect())
+}
@@ -0,0 +1,248 @@
+use std::{
+    ffi::OsStr,
+    fmt::Display,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+use serde::Deserialize;
+
+use crate::error::ClypperError;
+
+///How far the output's duration may be from the clip's before it counts as wrong
+const DEFAULT_TOLERANCE_MS: u64 = 500;
+
+///
+/// What an encoded clip is expected to look like. Anything left unset isn't checked.
+///
+/// ```
+/// use clypperlib::download::verify::Verification;
+///
+/// let verification = Verification::new(10_000).video_codec("h264").audio_codec("aac").resolution(1920, 1080);
+/// ```
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verification{
+    pub duration_ms: u64,
+    pub tolerance_ms: u64,
+    pub require_video: bool,
+    pub require_audio: bool,
+    ///As ffprobe names it, e.g. `h264` rather than the encoder's `libx264`
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+}
+
+impl Verification{
+    ///
+    /// Expects a video and an audio stream and a duration of `duration_ms`
+    ///
+    pub fn new(duration_ms: u64) -> Self{
+        Self{
+            duration_ms,
+            tolerance_ms: DEFAULT_TOLERANCE_MS,
+            require_video: true,
+            require_audio: true,
+            video_codec: None,
+            audio_codec: None,
+            resolution: None,
+        }
+    }
+
+    pub fn tolerance_ms(mut self, tolerance_ms: u64) -> Self{
+        self.tolerance_ms = tolerance_ms;
+        self
+    }
+
+    pub fn video_codec(mut self, codec: impl Into<String>) -> Self{
+        self.video_codec = Some(codec.into());
+        self
+    }
+
+    pub fn audio_codec(mut self, codec: impl Into<String>) -> Self{
+        self.audio_codec = Some(codec.into());
+        self
+    }
+
+    pub fn resolution(mut self, width: u32, height: u32) -> Self{
+        self.resolution = Some((width, height));
+        self
+    }
+
+    pub fn audio_only(mut self) -> Self{
+        self.require_video = false;
+        self
+    }
+
+    ///
+    /// Runs `ffprobe` on `file` and checks it
+    ///
+    pub fn verify(&self, ffprobe: &Path, file: &Path) -> Result<VerificationReport, ClypperError>{
+        let output = run_ffprobe(ffprobe, &["-print_format", "json", "-show_format", "-show_streams"], file)?;
+        if !output.status.success(){
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Ok(VerificationReport{
+                problems: vec![VerificationProblem::Unreadable(stderr.trim().to_string())],
+                ..VerificationReport::default()
+            });
+        }
+        let probe: ProbeOutput = serde_json::from_slice(&output.stdout)
+            .map_err(|err| ClypperError::InvalidField("ffprobe", err.to_string()))?;
+        Ok(self.check(&probe))
+    }
+
+    ///
+    /// Checks already parsed ffprobe output
+    ///
+    pub fn check(&self, probe: &ProbeOutput) -> VerificationReport{
+        let video = probe.streams.iter().find(|stream| stream.codec_type == "video").cloned();
+        let audio = probe.streams.iter().find(|stream| stream.codec_type == "audio").cloned();
+        let duration_ms = probe.format.as_ref()
+            .and_then(|format| format.duration.as_deref())
+            .or_else(|| video.as_ref().or(audio.as_ref())?.duration.as_deref())
+            .and_then(|duration| duration.parse::<f64>().ok())
+            .map(|seconds| (seconds * 1000.0).round() as u64);
+
+        let mut problems = vec![];
+        match video{
+            None if self.require_video => problems.push(VerificationProblem::MissingVideo),
+            Some(ref video) => {
+                if let Some(ref expected) = self.video_codec{
+                    if video.codec_name != *expected{
+                        problems.push(VerificationProblem::VideoCodec{ expected: expected.clone(), actual: video.codec_name.clone() });
+                    }
+                }
+                if let Some(expected) = self.resolution{
+                    let actual = (video.width.unwrap_or(0), video.height.unwrap_or(0));
+                    if actual != expected{
+                        problems.push(VerificationProblem::Resolution{ expected, actual });
+                    }
+                }
+            },
+            None => {},
+        }
+        match audio{
+            None if self.require_audio => problems.push(VerificationProblem::MissingAudio),
+            Some(ref audio) => {
+                if let Some(ref expected) = self.audio_codec{
+                    if audio.codec_name != *expected{
+                        problems.push(VerificationProblem::AudioCodec{ expected: expected.clone(), actual: audio.codec_name.clone() });
+                    }
+                }
+            },
+            None => {},
+        }
+        match duration_ms{
+            Some(actual_ms) if actual_ms.abs_diff(self.duration_ms) <= self.tolerance_ms => {},
+            actual_ms => problems.push(VerificationProblem::Duration{ expected_ms: self.duration_ms, actual_ms }),
+        }
+        VerificationReport{
+            duration_ms,
+            video,
+            audio,
+            problems,
+        }
+    }
+}
+
+///
+/// Runs `ffprobe -v error` with `args` on `input`, a file or url
+///
+pub(crate) fn run_ffprobe(ffprobe: &Path, args: &[&str], input: impl AsRef<OsStr>) -> Result<Output, ClypperError>{
+    Command::new(ffprobe)
+        .args(["-v", "error"])
+        .args(args)
+        .arg(input)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| match err.kind(){
+            std::io::ErrorKind::NotFound => ClypperError::FFmpegMissing(ffprobe.display().to_string()),
+            _ => ClypperError::FFmpegSpawn(err),
+        })
+}
+
+///
+/// What ffprobe found in an encoded clip and everything that didn't match the [`Verification`]
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerificationReport{
+    pub duration_ms: Option<u64>,
+    pub video: Option<ProbeStream>,
+    pub audio: Option<ProbeStream>,
+    pub problems: Vec<VerificationProblem>,
+}
+
+impl VerificationReport{
+    pub fn is_ok(&self) -> bool{
+        self.problems.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerificationProblem{
+    ///ffprobe couldn't read the file at all. args: ffprobe's error
+    Unreadable(String),
+    MissingVideo,
+    MissingAudio,
+    Duration{
+        expected_ms: u64,
+        ///`None` if ffprobe couldn't tell
+        actual_ms: Option<u64>,
+    },
+    VideoCodec{
+        expected: String,
+        actual: String,
+    },
+    AudioCodec{
+        expected: String,
+        actual: String,
+    },
+    Resolution{
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+}
+
+impl Display for VerificationProblem{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Self::Unreadable(message) => write!(f, "ffprobe can't read the file: {}", message),
+            Self::MissingVideo => write!(f, "no video stream"),
+            Self::MissingAudio => write!(f, "no audio stream"),
+            Self::Duration{ expected_ms, actual_ms: Some(actual_ms) } => write!(f, "expected {}ms long, got {}ms", expected_ms, actual_ms),
+            Self::Duration{ expected_ms, actual_ms: None } => write!(f, "expected {}ms long, got no duration", expected_ms),
+            Self::VideoCodec{ expected, actual } => write!(f, "expected {} video, got {}", expected, actual),
+            Self::AudioCodec{ expected, actual } => write!(f, "expected {} audio, got {}", expected, actual),
+            Self::Resolution{ expected, actual } => write!(f, "expected {}x{}, got {}x{}", expected.0, expected.1, actual.0, actual.1),
+        }
+    }
+}
+
+///
+/// The parts of `ffprobe -print_format json -show_format -show_streams` we look at
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ProbeOutput{
+    #[serde(default)]
+    pub streams: Vec<ProbeStream>,
+    pub format: Option<ProbeFormat>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct ProbeStream{
+    #[serde(default)]
+    pub codec_type: String,
+    #[serde(default)]
+    pub codec_name: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    ///e.g. `yuv420p`, video only
+    pub pix_fmt: Option<String>,
+    ///Seconds, as a string like `"10.000000"`
+    pub duration: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ProbeFormat{
+    pub duration: Option<String>,
+}
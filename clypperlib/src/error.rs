@@ -0,0 +1,156 @@
+use std::{error::Error, fmt::Display, io};
+
+use crate::download::verify::VerificationReport;
+
+///
+/// Every fallible operation in clypperlib reports one of these
+///
+#[derive(Debug)]
+pub enum ClypperError{
+    ///The request never got a response. args: message
+    Network(String),
+    ///The server answered with a non-success status. args: url, status code
+    HttpStatus(String, u32),
+    ///A field the extractor needs was not found on the page. args: field name
+    MissingField(&'static str),
+    ///A field was found but could not be understood. args: field name, value
+    InvalidField(&'static str, String),
+    ///No registered extractor handles this url. args: url
+    UnsupportedUrl(String),
+    ///The extractor can't do what was asked of it. args: message
+    Unsupported(String),
+    ///The clip is private, removed or otherwise unplayable. args: reason given by the site
+    ClipUnavailable(String),
+    ///None of the clip's formats satisfy the format selector. args: message
+    NoMatchingFormat(String),
+    ///The clip's source video has no caption track in the requested language. args: language
+    NoCaptions(String),
+    ///Speech to text failed. args: message
+    Transcription(String),
+    ///A stream url expired, or was refused with 403, and could not be refreshed. args: url, or
+    ///every input when ffmpeg didn't say which
+    StreamExpired(String),
+    ///No ffmpeg binary was found. args: where we looked
+    FFmpegMissing(String),
+    ///The ffmpeg that was found can't do what was asked of it. args: message
+    FFmpegUnsupported(String),
+    ///The ffmpeg process could not be started
+    FFmpegSpawn(io::Error),
+    ///ffmpeg ran but did not exit successfully
+    FFmpegExit{
+        ///Exit code, `None` if ffmpeg was killed by a signal
+        status: Option<i32>,
+        ///What went wrong, as far as we can tell from ffmpeg's output
+        failure: FFmpegFailure,
+        ///The last lines ffmpeg wrote to stderr, progress excluded
+        stderr_tail: Vec<String>,
+    },
+    ///Something went wrong talking to a running ffmpeg process. args: message
+    FFmpeg(String),
+    ///A filter graph's labels or stream types don't fit together. args: message
+    FilterGraph(String),
+    ///ffmpeg succeeded but ffprobe found the output isn't what was asked for. args: what ffprobe found
+    VerificationFailed(Box<VerificationReport>),
+    ///The job was cancelled before it finished
+    Cancelled,
+    Io(io::Error),
+}
+
+///
+/// The common ways an ffmpeg run fails, picked out of its stderr
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FFmpegFailure{
+    ///The server refused to serve an input, usually an expired stream url. args: url, `None` if
+    ///ffmpeg didn't say which
+    InputForbidden(Option<String>),
+    ///args: url, `None` if ffmpeg didn't say which
+    InputNotFound(Option<String>),
+    ///This ffmpeg build doesn't have the requested encoder. args: encoder name
+    UnknownEncoder(String),
+    DiskFull,
+    ///The output path can't be written to. args: ffmpeg's message
+    InvalidOutput(String),
+    ///Nothing we recognise, see the stderr tail
+    #[default]
+    Unknown,
+}
+
+impl Display for FFmpegFailure{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Self::InputForbidden(Some(url)) => write!(f, "access to {} was forbidden", url),
+            Self::InputForbidden(None) => write!(f, "access to an input was forbidden"),
+            Self::InputNotFound(Some(url)) => write!(f, "{} was not found", url),
+            Self::InputNotFound(None) => write!(f, "an input was not found"),
+            Self::UnknownEncoder(encoder) => write!(f, "this ffmpeg has no {} encoder", encoder),
+            Self::DiskFull => write!(f, "no space left on device"),
+            Self::InvalidOutput(message) => write!(f, "can't write output: {}", message),
+            Self::Unknown => write!(f, "unknown failure"),
+        }
+    }
+}
+
+impl Display for ClypperError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Self::Network(message) => write!(f, "network error: {}", message),
+            Self::HttpStatus(url, status) => write!(f, "{} returned HTTP {}", url, status),
+            Self::MissingField(field) => write!(f, "could not find `{}` on the clip page", field),
+            Self::InvalidField(field, value) => write!(f, "invalid value for `{}`: {:?}", field, value),
+            Self::UnsupportedUrl(url) => write!(f, "no extractor supports {}", url),
+            Self::Unsupported(message) => write!(f, "unsupported: {}", message),
+            Self::ClipUnavailable(reason) => write!(f, "clip is unavailable: {}", reason),
+            Self::NoMatchingFormat(message) => write!(f, "no matching format: {}", message),
+            Self::NoCaptions(language) => write!(f, "no {} captions for this video", language),
+            Self::Transcription(message) => write!(f, "transcription failed: {}", message),
+            Self::StreamExpired(url) => write!(f, "stream url expired, extract the clip again: {}", url),
+            Self::FFmpegMissing(location) => write!(f, "could not find ffmpeg in {}", location),
+            Self::FFmpegUnsupported(message) => write!(f, "ffmpeg can't do this: {}", message),
+            Self::FFmpegSpawn(err) => write!(f, "failed to start ffmpeg: {}", err),
+            Self::FFmpegExit{ status, failure, stderr_tail } => {
+                match status{
+                    Some(code) => write!(f, "ffmpeg exited with status {}", code)?,
+                    None => write!(f, "ffmpeg was terminated by a signal")?,
+                }
+                match (failure, stderr_tail.last()){
+                    (FFmpegFailure::Unknown, Some(last)) => write!(f, ": {}", last),
+                    (FFmpegFailure::Unknown, None) => Ok(()),
+                    (failure, _) => write!(f, ": {}", failure),
+                }
+            },
+            Self::FFmpeg(message) => write!(f, "ffmpeg error: {}", message),
+            Self::FilterGraph(message) => write!(f, "invalid filter graph: {}", message),
+            Self::VerificationFailed(report) => {
+                write!(f, "output failed verification")?;
+                for (i, problem) in report.problems.iter().enumerate(){
+                    write!(f, "{}{}", if i == 0 { ": " } else { ", " }, problem)?;
+                }
+                Ok(())
+            },
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl Error for ClypperError{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self{
+            Self::FFmpegSpawn(err) | Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClypperError{
+    fn from(err: io::Error) -> Self{
+        Self::Io(err)
+    }
+}
+
+impl From<curl::Error> for ClypperError{
+    fn from(err: curl::Error) -> Self{
+        Self::Network(err.to_string())
+    }
+}
@@ -0,0 +1,209 @@
+use serde::Deserialize;
+
+use crate::error::ClypperError;
+
+use super::extractor::ClipTime;
+
+///Longest pause between words that doesn't end a cue
+const MAX_WORD_GAP_MS: u64 = 1000;
+
+///
+/// A caption track a site offers for a clip's source video. Fetch its cues with
+/// [`super::extractor::SiteExtractor::captions`].
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaptionTrack{
+    ///BCP 47 code, e.g. `en` or `pt-BR`
+    pub language: String,
+    ///What the site calls the track, e.g. `English (auto-generated)`
+    pub name: String,
+    pub url: String,
+    ///Made by speech recognition rather than by a person
+    pub auto_generated: bool,
+}
+
+///
+/// The track for `language` in `tracks`, preferring one made by a person. Auto-generated tracks
+/// are only picked when `auto_generated` is set. `en` also matches regional tracks like `en-GB`.
+///
+pub fn find_track<'t>(tracks: &'t [CaptionTrack], language: &str, auto_generated: bool) -> Option<&'t CaptionTrack>{
+    let matches = |track: &&CaptionTrack| {
+        track.language.eq_ignore_ascii_case(language)
+            || track.language.split('-').next().is_some_and(|base| base.eq_ignore_ascii_case(language))
+    };
+    tracks.iter().filter(matches).find(|track| !track.auto_generated)
+        .or_else(|| tracks.iter().filter(matches).find(|track| auto_generated && track.auto_generated))
+}
+
+///
+/// A word and when it's spoken, for tracks that time each word
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaptionWord{
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+}
+
+///
+/// One caption on screen from `start_ms` to `end_ms`. `text` may hold line breaks.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cue{
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+    ///Empty unless the track times each word, auto-generated ones usually do
+    pub words: Vec<CaptionWord>,
+}
+
+///
+/// A caption track's cues, in order
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Captions{
+    pub cues: Vec<Cue>,
+}
+
+impl Captions{
+    ///
+    /// Reads youtube's `json3` timed text format
+    ///
+    pub fn from_json3(json: &str) -> Result<Self, ClypperError>{
+        let timed_text: TimedText = serde_json::from_str(json)
+            .map_err(|err| ClypperError::InvalidField("captions", err.to_string()))?;
+        let mut cues: Vec<Cue> = vec![];
+        //Appended events only add the line break auto-generated tracks roll up on
+        for event in timed_text.events.into_iter().filter(|event| !event.append){
+            let start_ms = event.start_ms;
+            let end_ms = start_ms + event.duration_ms;
+            let text: String = event.segs.iter().map(|seg| seg.utf8.as_str()).collect();
+            let text = text.trim();
+            if text.is_empty() || end_ms <= start_ms{
+                continue;
+            }
+            let mut words: Vec<CaptionWord> = vec![];
+            if event.segs.len() > 1{
+                for seg in event.segs.iter().filter(|seg| !seg.utf8.trim().is_empty()){
+                    let word_start_ms = start_ms + seg.offset_ms;
+                    if let Some(previous) = words.last_mut(){
+                        previous.end_ms = word_start_ms;
+                    }
+                    words.push(CaptionWord{ start_ms: word_start_ms, end_ms, text: seg.utf8.trim().to_string() });
+                }
+            }
+            cues.push(Cue{ start_ms, end_ms, text: text.to_string(), words });
+        }
+        //Auto-generated lines stay up until the one after next replaces them, end each where the
+        //next begins so only one shows at a time
+        for i in 1..cues.len(){
+            let next_start_ms = cues[i].start_ms;
+            let cue = &mut cues[i - 1];
+            if cue.end_ms > next_start_ms{
+                cue.end_ms = next_start_ms.max(cue.start_ms);
+                if let Some(last) = cue.words.last_mut(){
+                    last.end_ms = cue.end_ms.max(last.start_ms);
+                }
+            }
+        }
+        cues.retain(|cue| cue.end_ms > cue.start_ms);
+        Ok(Self{ cues })
+    }
+
+    ///
+    /// Groups timed words, e.g. from speech recognition, into cues of at most `max_words`. A cue
+    /// also ends after a sentence does or where the speaker pauses.
+    ///
+    pub fn from_words(words: Vec<CaptionWord>, max_words: usize) -> Self{
+        let mut cues: Vec<Cue> = vec![];
+        let mut current: Vec<CaptionWord> = vec![];
+        let finish = |current: &mut Vec<CaptionWord>, cues: &mut Vec<Cue>|{
+            let (Some(first), Some(last)) = (current.first(), current.last()) else{
+                return;
+            };
+            let text = current.iter().map(|word| word.text.as_str()).collect::<Vec<_>>().join(" ");
+            cues.push(Cue{ start_ms: first.start_ms, end_ms: last.end_ms, text, words: std::mem::take(current) });
+        };
+        for word in words{
+            let text = word.text.trim();
+            if text.is_empty(){
+                continue;
+            }
+            if current.last().is_some_and(|last| word.start_ms.saturating_sub(last.end_ms) >= MAX_WORD_GAP_MS){
+                finish(&mut current, &mut cues);
+            }
+            let ends_sentence = text.ends_with(['.', '!', '?']);
+            current.push(CaptionWord{ text: text.to_string(), ..word });
+            if ends_sentence || current.len() >= max_words.max(1){
+                finish(&mut current, &mut cues);
+            }
+        }
+        finish(&mut current, &mut cues);
+        Self{ cues }
+    }
+
+    ///
+    /// The cues shown during `time`, retimed so the clip starts at 0. Cues running over either
+    /// end are cut short, and timed words outside of it are dropped.
+    ///
+    pub fn trim(&self, time: ClipTime) -> Self{
+        let ClipTime(start_ms, end_ms) = time;
+        let overlaps = |from: u64, to: u64| from < end_ms && to > start_ms;
+        let retime = |ms: u64| ms.clamp(start_ms, end_ms) - start_ms;
+        let cues = self.cues.iter()
+            .filter(|cue| overlaps(cue.start_ms, cue.end_ms))
+            .filter_map(|cue| {
+                let words: Vec<CaptionWord> = cue.words.iter()
+                    .filter(|word| overlaps(word.start_ms, word.end_ms))
+                    .map(|word| CaptionWord{ start_ms: retime(word.start_ms), end_ms: retime(word.end_ms), text: word.text.clone() })
+                    .collect();
+                if !cue.words.is_empty() && words.is_empty(){
+                    return None;
+                }
+                let text = if cue.words.is_empty() { cue.text.clone() } else { words.iter().map(|word| word.text.as_str()).collect::<Vec<_>>().join(" ") };
+                Some(Cue{ start_ms: retime(cue.start_ms), end_ms: retime(cue.end_ms), text, words })
+            })
+            .collect();
+        Self{ cues }
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.cues.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TimedText{
+    #[serde(default)]
+    events: Vec<TimedTextEvent>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TimedTextEvent{
+    #[serde(rename = "tStartMs", default)]
+    start_ms: u64,
+    #[serde(rename = "dDurationMs", default)]
+    duration_ms: u64,
+    #[serde(rename = "aAppend", default, deserialize_with = "flag")]
+    append: bool,
+    #[serde(default)]
+    segs: Vec<TimedTextSeg>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TimedTextSeg{
+    #[serde(default)]
+    utf8: String,
+    #[serde(rename = "tOffsetMs", default)]
+    offset_ms: u64,
+}
+
+///
+/// Youtube marks flags with a `1`
+///
+fn flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(u64::deserialize(deserializer)? != 0)
+}
@@ -1,97 +1,246 @@
-use curl::easy::Easy;
-use indicatif::{ProgressStyle, ProgressBar};
-use regex::Regex;
-use console::style;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::ClypperError;
+
+use super::{captions::{CaptionTrack, Captions}, format::{FormatList, FormatSelector}, metadata::ClipMetadata};
 
 ///
 /// This struct holds the start and end times in milliseconds of the clip 
 ///
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct ClipTime(pub u64, pub u64);
 
+impl ClipTime{
+    pub fn duration_ms(&self) -> u64{
+        self.1.saturating_sub(self.0)
+    }
+}
+
+///
+/// A range to cut from a full video rather than a clip someone already made
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment{
+    ///Explicit start and end times in milliseconds
+    Range(ClipTime),
+    ///Starts at the url's timestamp (`?t=`, or the beginning without one) and runs for this many milliseconds
+    Duration(u64),
+}
+
+impl Segment{
+    ///
+    /// Resolves the segment to start and end times, using `start_ms` as the start for [`Segment::Duration`]
+    ///
+    pub fn time(&self, start_ms: u64) -> ClipTime{
+        match *self{
+            Segment::Range(time) => time,
+            Segment::Duration(duration_ms) => ClipTime(start_ms, start_ms + duration_ms),
+        }
+    }
+}
+
+///
+/// Parses a timestamp into milliseconds. Accepts plain seconds (`90`, `90.5`), youtube's unit
+/// form (`1h2m3s`, `1m30s`, `45s`) and clock form (`1:02:03`, `01:30.250`).
+///
+pub fn parse_timestamp(value: &str) -> Option<u64>{
+    let value = value.trim();
+    if value.is_empty(){
+        return None;
+    }
+    let seconds = |value: &str| -> Option<u64>{
+        let seconds: f64 = value.parse().ok()?;
+        (seconds.is_finite() && seconds >= 0.0).then(|| (seconds * 1000.0).round() as u64)
+    };
+    if value.contains(':'){
+        let mut total = 0;
+        for part in value.split(':'){
+            total = total * 60 + seconds(part)?;
+        }
+        return Some(total);
+    }
+    if value.ends_with(['h', 'm', 's']){
+        let mut total = 0;
+        let mut number = String::new();
+        for c in value.chars(){
+            let unit = match c{
+                'h' => 60 * 60,
+                'm' => 60,
+                's' => 1,
+                _ => {
+                    number.push(c);
+                    continue;
+                },
+            };
+            total += seconds(number.as_str())? * unit;
+            number.clear();
+        }
+        return number.is_empty().then_some(total);
+    }
+    seconds(value)
+}
+
+///How close to its expiry a stream url is treated as already expired, so ffmpeg has time to read it
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+///
+/// The video and audio stream urls a clip is cut from
+///
 #[derive(Clone, Debug, Default)]
 pub struct ClipResource(pub String, pub String);
 
+impl ClipResource{
+    pub fn urls(&self) -> [&str; 2]{
+        [self.0.as_str(), self.1.as_str()]
+    }
+
+    ///
+    /// When the first of the two streams stops being served, if the urls say
+    ///
+    pub fn expires_at(&self) -> Option<SystemTime>{
+        self.urls().into_iter().filter_map(url_expiry).min()
+    }
+
+    pub fn is_expired(&self) -> bool{
+        self.urls().into_iter().any(is_url_expired)
+    }
+}
+
+///
+/// When a stream url expires, read from its `expire` parameter. Googlevideo puts it in the query
+/// for direct streams and in the path (`/expire/<timestamp>/`) for manifests.
+///
+pub fn url_expiry(url: &str) -> Option<SystemTime>{
+    let url = UrlParts::parse(url)?;
+    let expire = url.query_param("expire").or_else(|| {
+        let mut segments = url.segments();
+        segments.find(|segment| *segment == "expire")?;
+        segments.next()
+    })?;
+    let seconds: u64 = expire.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+///
+/// Whether `url` has expired or will within the next minute. Urls without an expiry never expire.
+///
+pub fn is_url_expired(url: &str) -> bool{
+    url_expiry(url).is_some_and(|expiry| expiry <= SystemTime::now() + EXPIRY_MARGIN)
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Clip<'url>{
     pub url: &'url str,
     pub resource: ClipResource,
+    ///The range to cut out of `resource`
     pub time: ClipTime,
+    ///Where the clip sits in the video it was taken from, if the site says
+    pub source_time: Option<ClipTime>,
+    ///Every format the site offers, `resource` holds the ones that were selected
+    pub formats: FormatList,
+    pub metadata: ClipMetadata,
+    ///The source video's caption tracks, timed from the start of the video like `source_time`
+    pub captions: Vec<CaptionTrack>,
 }
 
-#[derive(Clone, Debug)]
-pub enum ClypperError{
-    ///Message, Error code?
-    CurlError(String, usize),
-    ///args: message, pattern
-    RegexError(String, String),
-    FFmpegError,
+impl<'url> Clip<'url>{
+    ///
+    /// Replaces the selected streams with the ones `selector` picks from the clip's formats
+    ///
+    pub fn select(&mut self, selector: &FormatSelector) -> Result<&ClipResource, ClypperError>{
+        self.resource = self.formats.resource(selector)?;
+        Ok(&self.resource)
+    }
 }
 
-pub struct Extractor{
-    video_url_re: Regex,
-    audio_url_re: Regex,
-    timestamp_re: Regex,
+///
+/// Knows how to turn clip urls from one site into [`Clip`]s. Register implementations with an
+/// [`super::registry::ExtractorRegistry`] to make them available to the rest of clypper.
+///
+pub trait SiteExtractor: Send + Sync{
+    ///Short name for the site, e.g. `youtube`
+    fn name(&self) -> &'static str;
+
+    ///Whether `url` is something this extractor can handle
+    fn matches(&self, url: &str) -> bool;
+
+    ///Extracts the clip at `url`, downloading it from the formats `selector` picks
+    fn extract_with<'url>(&self, url: &'url str, selector: &FormatSelector) -> Result<Clip<'url>, ClypperError>;
 
-    spinner: ProgressBar,
+    fn extract<'url>(&self, url: &'url str) -> Result<Clip<'url>, ClypperError>{
+        self.extract_with(url, &FormatSelector::default())
+    }
+
+    ///
+    /// Extracts `segment` of the video at `url`, which doesn't have to be a clip. Sites that
+    /// can't do this fail with [`ClypperError::Unsupported`].
+    ///
+    fn extract_segment<'url>(&self, url: &'url str, segment: Segment, selector: &FormatSelector) -> Result<Clip<'url>, ClypperError>{
+        let _ = (url, segment, selector);
+        Err(ClypperError::Unsupported(format!("{} does not support cutting arbitrary segments", self.name())))
+    }
+
+    ///
+    /// Downloads the cues of `track`, one of a clip's [`Clip::captions`]. Sites without captions
+    /// fail with [`ClypperError::Unsupported`].
+    ///
+    fn captions(&self, track: &CaptionTrack) -> Result<Captions, ClypperError>{
+        let _ = track;
+        Err(ClypperError::Unsupported(format!("{} does not have captions", self.name())))
+    }
 }
 
-impl Extractor{
-    fn get_html(&self, url: &str) -> Result<String, ClypperError>{
-        let mut easy = Easy::new();
-        easy.url(url).unwrap();
-        let mut buffer = Vec::new();
-        
-        {
-            let mut transfer = easy.transfer();
-            transfer
-                .write_function(|data| {
-                    buffer.extend_from_slice(data);
-                    Ok(data.len())
-                })
-                .unwrap();
-            transfer.perform().unwrap();
+///
+/// The pieces of a url extractors care about
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UrlParts<'url>{
+    ///Lowercase host without port, e.g. `www.youtube.com`
+    pub host: &'url str,
+    ///Path starting with `/`, without the query
+    pub path: &'url str,
+    pub query: &'url str,
+}
+
+impl<'url> UrlParts<'url>{
+    pub fn parse(url: &'url str) -> Option<Self>{
+        let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let rest = rest.split_once('#').map_or(rest, |(rest, _)| rest);
+        let (authority, path_and_query) = match rest.find(['/', '?']){
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+        let host = host.split_once(':').map_or(host, |(host, _)| host);
+        if host.is_empty(){
+            return None;
         }
-        let buffer = buffer.clone();
-        let html = String::from_utf8(buffer).unwrap();
-        Ok(html)
+        let (path, query) = path_and_query.split_once('?').unwrap_or((path_and_query, ""));
+        Some(Self{
+            host,
+            path: if path.is_empty() { "/" } else { path },
+            query,
+        })
     }
 
-    pub fn new() -> Result<Self, ClypperError>{
-        let style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}").unwrap();
-        let spinner = ProgressBar::new_spinner();
-        spinner.set_style(style);
+    ///
+    /// Whether the host is `domain` or one of its subdomains
+    ///
+    pub fn is_host(&self, domain: &str) -> bool{
+        self.host.eq_ignore_ascii_case(domain)
+            || (self.host.len() > domain.len()
+                && self.host[self.host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+                && self.host.as_bytes()[self.host.len() - domain.len() - 1] == b'.')
+    }
 
-        Ok(Self{
-            video_url_re: Regex::new(r#"\"itag\":\d+,\"url\":\"(.+?)\".+?\"width\":(\d+)"#).unwrap(),
-            audio_url_re: Regex::new(r#"itag\":\d+,\"url\":\"([^\"\s]*)\",\"mimeType\":\"audio/mp4;"#).unwrap(),
-            timestamp_re: Regex::new(r#"\"clipConfig\":\{\"postId\":\".+\",\"startTimeMs\":\"(\d+?)\",\"endTimeMs\":\"(\d+?)\""#).unwrap(), 
-            spinner,
-        })
+    pub fn query_param(&self, name: &str) -> Option<&'url str>{
+        self.query.split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
     }
 
-    pub fn extract<'a>(&'a self, url: &'a str) -> Result<Clip, ClypperError>{
-        self.spinner.set_message("Getting clip info...");
-        let html_result = self.get_html(url).unwrap();
-        let html = html_result.as_str();
-        let mut video_url = String::new();
-        //TODO: Add more proper error handling
-        for (_, [url, width]) in self.video_url_re.captures_iter(html).map(|c| c.extract()){
-            if width == "1920"{
-                video_url = String::from(url);
-            }
-        };
-        //TODO: Add more proper error handling
-        let audio_url = self.audio_url_re.captures(html).unwrap().get(1).unwrap();
-        let escape = Regex::new(r#"\\u0026"#).unwrap();
-        let vid_url = String::from(escape.replace_all(video_url.as_str(), "&"));
-        let aud_url = String::from(escape.replace_all(audio_url.as_str(), "&"));
-
-        let timestamp_match = self.timestamp_re.captures(html).unwrap();
-        let start_ms: u64 = timestamp_match.get(1).unwrap().as_str().parse().unwrap();
-        let end_ms: u64 = timestamp_match.get(2).unwrap().as_str().parse().unwrap();
-        self.spinner.finish_with_message("Getting clip info... Done!");
-        Ok(Clip { url, resource: ClipResource(vid_url, aud_url), time: ClipTime(start_ms, end_ms) })
+    pub fn segments(&self) -> impl Iterator<Item = &'url str>{
+        self.path.split('/').filter(|segment| !segment.is_empty())
     }
 }
-
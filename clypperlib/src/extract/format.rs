@@ -0,0 +1,261 @@
+use crate::error::ClypperError;
+
+use super::extractor::ClipResource;
+
+const VIDEO_CODECS: [&str; 6] = ["avc1", "vp9", "vp09", "av01", "hev1", "hvc1"];
+const AUDIO_CODECS: [&str; 5] = ["mp4a", "opus", "vorbis", "ac-3", "ec-3"];
+
+///
+/// A single stream offered by the site, either video, audio or both muxed together
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Format{
+    pub itag: u32,
+    pub url: String,
+    ///e.g. `mp4` or `webm`
+    pub container: String,
+    ///The raw codecs string, e.g. `avc1.640028` or `avc1.42001E, mp4a.40.2` for muxed streams
+    pub codec: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    ///Average bitrate in bits per second
+    pub bitrate: Option<u64>,
+    pub audio_channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+    ///Size of the whole stream in bytes
+    pub content_length: Option<u64>,
+}
+
+impl Format{
+    ///
+    /// Splits a mime type like `video/mp4; codecs="avc1.640028"` into its container and codecs
+    ///
+    pub fn parse_mime_type(mime_type: &str) -> (String, String){
+        let (essence, params) = mime_type.split_once(';').unwrap_or((mime_type, ""));
+        let container = essence.split_once('/').map_or(essence, |(_, subtype)| subtype).trim();
+        let codec = params.trim()
+            .strip_prefix("codecs=")
+            .unwrap_or("")
+            .trim_matches('"');
+        (container.to_string(), codec.to_string())
+    }
+
+    pub fn has_video(&self) -> bool{
+        self.width.is_some() || self.height.is_some() || self.video_codec().is_some()
+    }
+
+    pub fn has_audio(&self) -> bool{
+        self.audio_channels.is_some() || self.sample_rate.is_some() || self.audio_codec().is_some()
+    }
+
+    pub fn is_muxed(&self) -> bool{
+        self.has_video() && self.has_audio()
+    }
+
+    pub fn video_codec(&self) -> Option<&str>{
+        self.codecs().find(|codec| VIDEO_CODECS.iter().any(|prefix| codec.starts_with(prefix)))
+    }
+
+    pub fn audio_codec(&self) -> Option<&str>{
+        self.codecs().find(|codec| AUDIO_CODECS.iter().any(|prefix| codec.starts_with(prefix)))
+    }
+
+    ///
+    /// The "p" rating of the stream, i.e. the short side of the frame, so vertical sources
+    /// compare the same as horizontal ones
+    ///
+    pub fn resolution(&self) -> u32{
+        match (self.width, self.height){
+            (Some(width), Some(height)) => width.min(height),
+            (width, height) => width.or(height).unwrap_or(0),
+        }
+    }
+
+    fn codecs(&self) -> impl Iterator<Item = &str>{
+        self.codec.split(',').map(str::trim).filter(|codec| !codec.is_empty())
+    }
+
+    fn size(&self) -> u64{
+        self.content_length.or(self.bitrate).unwrap_or(u64::MAX)
+    }
+}
+
+///
+/// Every format found for a clip
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FormatList(pub Vec<Format>);
+
+impl FormatList{
+    pub fn iter(&self) -> impl Iterator<Item = &Format>{
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, itag: u32) -> Option<&Format>{
+        self.iter().find(|format| format.itag == itag)
+    }
+
+    pub fn video(&self) -> impl Iterator<Item = &Format>{
+        self.iter().filter(|format| format.has_video())
+    }
+
+    pub fn audio(&self) -> impl Iterator<Item = &Format>{
+        self.iter().filter(|format| format.has_audio())
+    }
+
+    ///
+    /// Picks a video and an audio format according to `selector`
+    ///
+    pub fn select(&self, selector: &FormatSelector) -> Result<(&Format, &Format), ClypperError>{
+        let video = selector.select_video(self)?;
+        let audio = selector.select_audio(self, video)?;
+        Ok((video, audio))
+    }
+
+    ///
+    /// Same as [`FormatList::select`] but only keeps the stream urls
+    ///
+    pub fn resource(&self, selector: &FormatSelector) -> Result<ClipResource, ClypperError>{
+        let (video, audio) = self.select(selector)?;
+        Ok(ClipResource(video.url.clone(), audio.url.clone()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quality{
+    #[default]
+    Best,
+    Smallest,
+}
+
+///
+/// Policy used to pick which formats a clip is downloaded from
+///
+/// ```
+/// use clypperlib::extract::format::FormatSelector;
+///
+/// let selector = FormatSelector::best_up_to(1080).prefer_codec("avc1");
+/// ```
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormatSelector{
+    pub quality: Quality,
+    ///Upper bound on the short side of the frame, e.g. 1080 for "1080p"
+    pub max_resolution: Option<u32>,
+    ///Codec prefix to prefer between formats of the same resolution, e.g. `avc1`
+    pub prefer_codec: Option<String>,
+    pub itag: Option<u32>,
+    pub audio_itag: Option<u32>,
+}
+
+impl Default for FormatSelector{
+    fn default() -> Self{
+        Self::best_up_to(1080).prefer_codec("avc1")
+    }
+}
+
+impl FormatSelector{
+    pub fn best() -> Self{
+        Self{
+            quality: Quality::Best,
+            max_resolution: None,
+            prefer_codec: None,
+            itag: None,
+            audio_itag: None,
+        }
+    }
+
+    pub fn best_up_to(max_resolution: u32) -> Self{
+        Self{
+            max_resolution: Some(max_resolution),
+            ..Self::best()
+        }
+    }
+
+    pub fn smallest() -> Self{
+        Self{
+            quality: Quality::Smallest,
+            ..Self::best()
+        }
+    }
+
+    pub fn itag(itag: u32) -> Self{
+        Self{
+            itag: Some(itag),
+            ..Self::best()
+        }
+    }
+
+    pub fn prefer_codec(mut self, codec: impl Into<String>) -> Self{
+        self.prefer_codec = Some(codec.into());
+        self
+    }
+
+    pub fn audio_itag(mut self, itag: u32) -> Self{
+        self.audio_itag = Some(itag);
+        self
+    }
+
+    fn select_video<'a>(&self, formats: &'a FormatList) -> Result<&'a Format, ClypperError>{
+        if let Some(itag) = self.itag{
+            return formats.get(itag)
+                .filter(|format| format.has_video())
+                .ok_or_else(|| ClypperError::NoMatchingFormat(format!("no video format with itag {}", itag)));
+        }
+        let mut candidates: Vec<&Format> = formats.video().collect();
+        if let Some(max) = self.max_resolution{
+            let capped: Vec<&Format> = candidates.iter().copied().filter(|format| format.resolution() <= max).collect();
+            //Nothing small enough, settle for the closest thing above the cap
+            candidates = if capped.is_empty(){
+                let lowest = candidates.iter().map(|format| format.resolution()).min();
+                candidates.into_iter().filter(|format| Some(format.resolution()) == lowest).collect()
+            }else{
+                capped
+            };
+        }
+        //The codec only breaks ties, a 1080p vp9 stream still beats a 360p avc1 one
+        let best = match self.quality{
+            Quality::Best => candidates.into_iter().max_by_key(|format| {
+                (format.resolution(), self.is_preferred(format.video_codec()), format.fps.unwrap_or(0), format.bitrate.unwrap_or(0))
+            }),
+            Quality::Smallest => candidates.into_iter().min_by_key(|format| (format.resolution(), !self.is_preferred(format.video_codec()), format.size())),
+        };
+        best.ok_or_else(|| ClypperError::NoMatchingFormat("no video formats available".to_string()))
+    }
+
+    fn select_audio<'a>(&self, formats: &'a FormatList, video: &'a Format) -> Result<&'a Format, ClypperError>{
+        if let Some(itag) = self.audio_itag{
+            return formats.get(itag)
+                .filter(|format| format.has_audio())
+                .ok_or_else(|| ClypperError::NoMatchingFormat(format!("no audio format with itag {}", itag)));
+        }
+        let audio_only: Vec<&Format> = formats.audio().filter(|format| !format.has_video()).collect();
+        if audio_only.is_empty(){
+            return if video.has_audio(){
+                Ok(video)
+            }else{
+                Err(ClypperError::NoMatchingFormat("no audio formats available".to_string()))
+            };
+        }
+        //Keep audio in the same container as the video when we can
+        let same_container: Vec<&Format> = audio_only.iter().copied().filter(|format| format.container == video.container).collect();
+        let candidates = if same_container.is_empty(){ audio_only }else{ same_container };
+        let best = match self.quality{
+            Quality::Best => candidates.into_iter().max_by_key(|format| format.bitrate.unwrap_or(0)),
+            Quality::Smallest => candidates.into_iter().min_by_key(|format| format.size()),
+        };
+        best.ok_or_else(|| ClypperError::NoMatchingFormat("no audio formats available".to_string()))
+    }
+
+    fn is_preferred(&self, codec: Option<&str>) -> bool{
+        match (&self.prefer_codec, codec){
+            (Some(prefix), Some(codec)) => codec.starts_with(prefix.as_str()),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use curl::easy::{Easy, List};
+
+use crate::error::ClypperError;
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Method{
+    #[default]
+    Get,
+    Post,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpRequest{
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl HttpRequest{
+    pub fn get(url: impl Into<String>) -> Self{
+        Self{
+            url: url.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn post(url: impl Into<String>, body: impl Into<Vec<u8>>) -> Self{
+        Self{
+            method: Method::Post,
+            url: url.into(),
+            headers: vec![],
+            body: Some(body.into()),
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self{
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpResponse{
+    pub status: u32,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse{
+    pub fn new(status: u32, body: impl Into<Vec<u8>>) -> Self{
+        Self{
+            status,
+            body: body.into(),
+        }
+    }
+
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self{
+        Self::new(200, body)
+    }
+
+    pub fn not_found() -> Self{
+        Self::new(404, "not found")
+    }
+
+    pub fn is_success(&self) -> bool{
+        (200..300).contains(&self.status)
+    }
+
+    pub fn text(&self) -> Result<String, ClypperError>{
+        String::from_utf8(self.body.clone())
+            .map_err(|err| ClypperError::Network(format!("response is not valid UTF-8: {}", err)))
+    }
+}
+
+///
+/// The transport extractors fetch pages with. [`CurlClient`] talks to the real sites,
+/// [`FixtureClient`] answers from memory so extraction can run offline.
+///
+pub trait HttpClient: Send + Sync{
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, ClypperError>;
+
+    ///
+    /// Sends `request` and fails with [`ClypperError::HttpStatus`] unless the server answered 2xx
+    ///
+    fn fetch(&self, request: &HttpRequest) -> Result<HttpResponse, ClypperError>{
+        let response = self.send(request)?;
+        if !response.is_success(){
+            return Err(ClypperError::HttpStatus(request.url.clone(), response.status));
+        }
+        Ok(response)
+    }
+
+    fn get_text(&self, url: &str) -> Result<String, ClypperError>{
+        self.fetch(&HttpRequest::get(url))?.text()
+    }
+}
+
+impl<T: HttpClient + ?Sized> HttpClient for Box<T>{
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, ClypperError>{
+        (**self).send(request)
+    }
+}
+
+impl<T: HttpClient + ?Sized> HttpClient for Arc<T>{
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, ClypperError>{
+        (**self).send(request)
+    }
+}
+
+///
+/// Sends requests with libcurl
+///
+#[derive(Clone, Debug)]
+pub struct CurlClient{
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Default for CurlClient{
+    fn default() -> Self{
+        Self{
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: vec![("Accept-Language".to_string(), "en-US,en;q=0.9".to_string())],
+        }
+    }
+}
+
+impl CurlClient{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    ///
+    /// Gives up on a request that takes longer than `timeout` in total
+    ///
+    pub fn timeout(mut self, timeout: Duration) -> Self{
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self{
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self{
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    ///
+    /// Adds a header sent with every request
+    ///
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self{
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+impl HttpClient for CurlClient{
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, ClypperError>{
+        let mut easy = Easy::new();
+        easy.url(request.url.as_str())?;
+        easy.follow_location(true)?;
+        easy.timeout(self.timeout)?;
+        easy.connect_timeout(self.connect_timeout)?;
+        easy.useragent(self.user_agent.as_str())?;
+        let mut headers = List::new();
+        for (name, value) in self.headers.iter().chain(request.headers.iter()){
+            headers.append(format!("{}: {}", name, value).as_str())?;
+        }
+        easy.http_headers(headers)?;
+        if let Method::Post = request.method{
+            easy.post(true)?;
+            easy.post_fields_copy(request.body.as_deref().unwrap_or_default())?;
+        }
+
+        let mut body = Vec::new();
+        {
+            let mut transfer = easy.transfer();
+            transfer
+                .write_function(|data| {
+                    body.extend_from_slice(data);
+                    Ok(data.len())
+                })?;
+            transfer.perform()?;
+        }
+        Ok(HttpResponse{
+            status: easy.response_code()?,
+            body,
+        })
+    }
+}
+
+///
+/// Answers requests from canned responses, e.g. saved clip pages. Urls are matched exactly
+/// first, then by the longest registered prefix. Anything else gets a 404.
+///
+#[derive(Clone, Debug, Default)]
+pub struct FixtureClient{
+    routes: HashMap<String, HttpResponse>,
+}
+
+impl FixtureClient{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn route(mut self, url: impl Into<String>, response: HttpResponse) -> Self{
+        self.routes.insert(url.into(), response);
+        self
+    }
+
+    pub fn page(self, url: impl Into<String>, body: impl Into<Vec<u8>>) -> Self{
+        self.route(url, HttpResponse::ok(body))
+    }
+
+    ///
+    /// Serves the saved file at `path` for `url`
+    ///
+    pub fn file(self, url: impl Into<String>, path: impl AsRef<std::path::Path>) -> Result<Self, ClypperError>{
+        let body = std::fs::read(path)?;
+        Ok(self.page(url, body))
+    }
+}
+
+impl HttpClient for FixtureClient{
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, ClypperError>{
+        let url = request.url.as_str();
+        let response = self.routes.get(url).or_else(|| {
+            self.routes.iter()
+                .filter(|(route, _)| url.starts_with(route.as_str()))
+                .max_by_key(|(route, _)| route.len())
+                .map(|(_, response)| response)
+        });
+        Ok(response.cloned().unwrap_or_else(HttpResponse::not_found))
+    }
+}
+
+type Routes = Arc<Mutex<HashMap<String, HttpResponse>>>;
+
+///
+/// A tiny HTTP server on localhost for standing in for the real sites, including for ffmpeg,
+/// which needs real urls to read streams from. Routes are matched by path (query included if
+/// registered that way). Stops when dropped.
+///
+pub struct FixtureServer{
+    address: SocketAddr,
+    routes: Routes,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FixtureServer{
+    pub fn start() -> Result<Self, ClypperError>{
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let routes = Routes::default();
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread_routes = routes.clone();
+        let thread_shutdown = shutdown.clone();
+        let thread = thread::spawn(move || {
+            while !thread_shutdown.load(Ordering::Relaxed){
+                match listener.accept(){
+                    Ok((stream, _)) => {
+                        let routes = thread_routes.clone();
+                        thread::spawn(move || {
+                            //NOTE: The client hanging up early is not our problem
+                            let _ = Self::serve(stream, &routes);
+                        });
+                    },
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            }
+        });
+        Ok(Self{
+            address,
+            routes,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    ///
+    /// The full url for `path` on this server
+    ///
+    pub fn url(&self, path: &str) -> String{
+        format!("http://{}{}", self.address, path)
+    }
+
+    pub fn route(&self, path: impl Into<String>, response: HttpResponse) -> &Self{
+        //NOTE: Connection threads only panic while holding the lock if the routes are corrupt anyway
+        self.routes.lock().unwrap().insert(path.into(), response);
+        self
+    }
+
+    pub fn file(&self, path: impl Into<String>, file: impl AsRef<std::path::Path>) -> Result<&Self, ClypperError>{
+        let body = std::fs::read(file)?;
+        Ok(self.route(path, HttpResponse::ok(body)))
+    }
+
+    fn serve(stream: TcpStream, routes: &Routes) -> Result<(), ClypperError>{
+        stream.set_nonblocking(false)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or("/").to_string();
+
+        let mut content_length = 0;
+        let mut range = None;
+        loop{
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty(){
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else{
+                continue;
+            };
+            match name.trim().to_ascii_lowercase().as_str(){
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "range" => range = value.trim().strip_prefix("bytes=").map(str::to_string),
+                _ => {}
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let response = {
+            let routes = routes.lock().unwrap();
+            let path = target.split_once('?').map_or(target.as_str(), |(path, _)| path);
+            routes.get(target.as_str()).or_else(|| routes.get(path)).cloned()
+        }.unwrap_or_else(HttpResponse::not_found);
+
+        let total = response.body.len();
+        let (status, start, end) = match range.as_deref().and_then(|range| range.split_once('-')){
+            Some((start, end)) if response.is_success() => {
+                let start = start.parse().unwrap_or(0).min(total);
+                let end = end.parse::<usize>().map_or(total, |end| (end + 1).min(total));
+                (206, start, end.max(start))
+            },
+            _ => (response.status, 0, total),
+        };
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {} {}\r\n", status, if status < 300 { "OK" } else { "Error" })?;
+        write!(stream, "Content-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n", end - start)?;
+        if status == 206{
+            write!(stream, "Content-Range: bytes {}-{}/{}\r\n", start, end.saturating_sub(1), total)?;
+        }
+        write!(stream, "\r\n")?;
+        if method != "HEAD"{
+            stream.write_all(&response.body[start..end])?;
+        }
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for FixtureServer{
+    fn drop(&mut self){
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take(){
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,111 @@
+///
+/// A thumbnail image, sizes in pixels when the site gives them
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Thumbnail{
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+///
+/// Everything we know about a clip besides its streams. Sites leave out whatever they don't
+/// publish, so every field is optional.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClipMetadata{
+    ///The clip's own title, which may differ from the video's
+    pub title: Option<String>,
+    ///Who made the clip
+    pub creator: Option<String>,
+    pub source_video_id: Option<String>,
+    pub source_title: Option<String>,
+    pub channel_name: Option<String>,
+    pub channel_id: Option<String>,
+    ///When the source video was uploaded or streamed, as an ISO 8601 date or timestamp
+    pub date: Option<String>,
+    pub source_duration_ms: Option<u64>,
+    pub view_count: Option<u64>,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+impl ClipMetadata{
+    ///
+    /// The clip title, falling back to the source video's
+    ///
+    pub fn display_title(&self) -> Option<&str>{
+        self.title.as_deref().or(self.source_title.as_deref())
+    }
+
+    ///
+    /// The largest thumbnail on offer
+    ///
+    pub fn best_thumbnail(&self) -> Option<&Thumbnail>{
+        self.thumbnails.iter().max_by_key(|thumbnail| thumbnail.width.unwrap_or(0) * thumbnail.height.unwrap_or(0))
+    }
+
+    ///
+    /// Fills in `template` and makes the result safe to use as a file name. Understands
+    /// `{title}`, `{source_title}`, `{creator}`, `{channel}`, `{channel_id}`, `{video_id}` and
+    /// `{date}`. Unknown values become `unknown`. A name with nothing left once sanitized falls
+    /// back to the video id, or `clip`.
+    ///
+    /// ```
+    /// use clypperlib::extract::metadata::ClipMetadata;
+    ///
+    /// let metadata = ClipMetadata{ title: Some("gg/ez?".to_string()), channel_name: Some("Streamer".to_string()), ..ClipMetadata::default() };
+    /// assert_eq!(metadata.file_name("{channel} - {title}"), "Streamer - gg_ez_");
+    /// ```
+    ///
+    pub fn file_name(&self, template: &str) -> String{
+        let date = self.date.as_deref().map(|date| date.split('T').next().unwrap_or(date));
+        let field = |key: &str| match key{
+            "title" => Some(self.display_title()),
+            "source_title" => Some(self.source_title.as_deref()),
+            "creator" => Some(self.creator.as_deref()),
+            "channel" => Some(self.channel_name.as_deref()),
+            "channel_id" => Some(self.channel_id.as_deref()),
+            "video_id" => Some(self.source_video_id.as_deref()),
+            "date" => Some(date),
+            _ => None,
+        };
+        //One pass over the template, so a title containing `{date}` stays as it is
+        let mut name = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{'){
+            name.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let value = rest.find('}').and_then(|end| field(&rest[1..end]).map(|value| (end, value)));
+            match value{
+                Some((end, value)) => {
+                    name.push_str(value.unwrap_or("unknown"));
+                    rest = &rest[end + 1..];
+                },
+                None => {
+                    name.push('{');
+                    rest = &rest[1..];
+                },
+            }
+        }
+        name.push_str(rest);
+        let name = sanitize(name.as_str());
+        if !name.is_empty(){
+            return name;
+        }
+        self.source_video_id.as_deref()
+            .map(sanitize)
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| "clip".to_string())
+    }
+}
+
+///
+/// Replaces what file systems refuse in a name and trims the spaces and dots Windows drops
+///
+fn sanitize(name: &str) -> String{
+    name.chars()
+        .map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect::<String>()
+        .trim_matches([' ', '.'])
+        .to_string()
+}
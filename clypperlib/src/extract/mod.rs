@@ -1 +1,17 @@
+//!
+//! Turns clip page urls into [`extractor::Clip`]s describing the streams and time range to cut.
+//!
+//! Each supported site implements [`extractor::SiteExtractor`], and
+//! [`registry::ExtractorRegistry`] picks the right one for a url. Pages are fetched through
+//! [`http::HttpClient`], so extraction can be pointed at saved pages. Clips list the caption
+//! tracks of their source video, see [`captions`].
+//!
+pub mod captions;
 pub mod extractor;
+pub mod format;
+pub mod http;
+pub mod metadata;
+pub mod player;
+pub mod registry;
+pub mod twitch;
+pub mod youtube;
@@ -0,0 +1,371 @@
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::error::ClypperError;
+
+use super::{captions::CaptionTrack, format::Format, metadata::{ClipMetadata, Thumbnail}};
+
+///
+/// The JSON blobs youtube embeds in a watch or clip page
+///
+#[derive(Clone, Debug)]
+pub struct PlayerPage{
+    pub player_response: PlayerResponse,
+    ///`ytInitialData`, kept untyped since we only ever dig a few values out of it
+    pub initial_data: Option<Value>,
+    ///The page's `og:title`, which is the clip title on clip pages
+    pub og_title: Option<String>,
+
+    raw_player_response: Value,
+}
+
+impl PlayerPage{
+    pub fn parse(html: &str) -> Result<Self, ClypperError>{
+        let raw_player_response: Value = find_json_blob(html, "ytInitialPlayerResponse")?
+            .ok_or(ClypperError::MissingField("ytInitialPlayerResponse"))?;
+        let player_response = serde_json::from_value(raw_player_response.clone())
+            .map_err(|err| ClypperError::InvalidField("ytInitialPlayerResponse", err.to_string()))?;
+        let initial_data = find_json_blob(html, "ytInitialData")?;
+        Ok(Self{
+            player_response,
+            initial_data,
+            og_title: find_meta(html, "og:title"),
+            raw_player_response,
+        })
+    }
+
+    ///
+    /// Fails with [`ClypperError::ClipUnavailable`] unless the video can be played
+    ///
+    pub fn check_playable(&self) -> Result<(), ClypperError>{
+        match self.player_response.playability_status{
+            Some(ref playability) if playability.status != "OK" => {
+                let reason = playability.reason.clone().unwrap_or_else(|| playability.status.clone());
+                Err(ClypperError::ClipUnavailable(reason))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// The clip's `clipConfig`, wherever youtube decided to put it this week
+    ///
+    pub fn clip_config(&self) -> Option<ClipConfig>{
+        [Some(&self.raw_player_response), self.initial_data.as_ref()]
+            .into_iter()
+            .flatten()
+            .find_map(|value| find_key(value, "clipConfig"))
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+
+    ///
+    /// Collects the clip's metadata. The clip title and creator only exist on clip pages, and
+    /// youtube doesn't publish them anywhere stable, so they are best effort.
+    ///
+    pub fn metadata(&self, is_clip: bool) -> ClipMetadata{
+        let details = self.player_response.video_details.clone().unwrap_or_default();
+        let microformat = self.player_response.microformat.as_ref()
+            .and_then(|microformat| microformat.player_microformat_renderer.clone())
+            .unwrap_or_default();
+        let attribution = self.initial_data.as_ref().and_then(|data| find_key(data, "clipAttributionRenderer"));
+        let (title, creator) = if is_clip{
+            let title = attribution.and_then(|attribution| attribution.get("title")).and_then(text).or_else(|| self.og_title.clone());
+            let creator = attribution.and_then(|attribution| attribution.get("createdBy")).and_then(text)
+                .map(|creator| creator.trim_start_matches("Clipped by").trim().to_string());
+            (title, creator)
+        }else{
+            (None, None)
+        };
+        let non_empty = |value: String| (!value.is_empty()).then_some(value);
+        let date = microformat.live_broadcast_details.and_then(|live| live.start_timestamp)
+            .or(microformat.publish_date)
+            .or(microformat.upload_date);
+        ClipMetadata{
+            title,
+            creator,
+            source_video_id: non_empty(details.video_id),
+            source_title: non_empty(details.title),
+            channel_name: non_empty(details.author).or(microformat.owner_channel_name),
+            channel_id: non_empty(details.channel_id),
+            date,
+            source_duration_ms: details.length_seconds.filter(|length| *length > 0).map(|length| length * 1000),
+            view_count: details.view_count,
+            thumbnails: details.thumbnail.thumbnails.into_iter().map(|thumbnail| Thumbnail{
+                url: thumbnail.url,
+                width: thumbnail.width,
+                height: thumbnail.height,
+            }).collect(),
+        }
+    }
+
+    ///
+    /// The caption tracks on offer, people's and youtube's speech recognition's
+    ///
+    pub fn caption_tracks(&self) -> Vec<CaptionTrack>{
+        let Some(ref captions) = self.player_response.captions else{
+            return vec![];
+        };
+        let Some(ref tracklist) = captions.player_captions_tracklist_renderer else{
+            return vec![];
+        };
+        tracklist.caption_tracks.iter()
+            .map(|track| CaptionTrack{
+                language: track.language_code.clone(),
+                name: track.name.as_ref().and_then(text).unwrap_or_else(|| track.language_code.clone()),
+                url: track.base_url.clone(),
+                auto_generated: track.kind.as_deref() == Some("asr"),
+            })
+            .collect()
+    }
+
+    pub fn formats(&self) -> Vec<Format>{
+        let Some(ref streaming_data) = self.player_response.streaming_data else{
+            return vec![];
+        };
+        streaming_data.formats.iter()
+            .chain(streaming_data.adaptive_formats.iter())
+            .filter_map(RawFormat::to_format)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerResponse{
+    pub playability_status: Option<PlayabilityStatus>,
+    pub streaming_data: Option<StreamingData>,
+    pub video_details: Option<VideoDetails>,
+    pub microformat: Option<Microformat>,
+    pub captions: Option<PlayerCaptions>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayabilityStatus{
+    pub status: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamingData{
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub expires_in_seconds: Option<u64>,
+    #[serde(default)]
+    pub formats: Vec<RawFormat>,
+    #[serde(default)]
+    pub adaptive_formats: Vec<RawFormat>,
+}
+
+///
+/// A format exactly as it appears in `streamingData`
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawFormat{
+    pub itag: u32,
+    pub url: Option<String>,
+    pub signature_cipher: Option<String>,
+    #[serde(default)]
+    pub mime_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub bitrate: Option<u64>,
+    pub average_bitrate: Option<u64>,
+    pub audio_channels: Option<u32>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub audio_sample_rate: Option<u64>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub content_length: Option<u64>,
+}
+
+impl RawFormat{
+    ///
+    /// `None` for formats whose url is hidden behind a signature cipher, which we don't support
+    ///
+    pub fn to_format(&self) -> Option<Format>{
+        let url = self.url.clone()?;
+        let (container, codec) = Format::parse_mime_type(self.mime_type.as_str());
+        Some(Format{
+            itag: self.itag,
+            url,
+            container,
+            codec,
+            width: self.width,
+            height: self.height,
+            fps: self.fps,
+            bitrate: self.average_bitrate.or(self.bitrate),
+            audio_channels: self.audio_channels,
+            sample_rate: self.audio_sample_rate.map(|rate| rate as u32),
+            content_length: self.content_length,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoDetails{
+    pub video_id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub length_seconds: Option<u64>,
+    #[serde(default)]
+    pub channel_id: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub view_count: Option<u64>,
+    #[serde(default)]
+    pub thumbnail: ThumbnailList,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ThumbnailList{
+    #[serde(default)]
+    pub thumbnails: Vec<RawThumbnail>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RawThumbnail{
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Microformat{
+    pub player_microformat_renderer: Option<PlayerMicroformat>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerMicroformat{
+    pub upload_date: Option<String>,
+    pub publish_date: Option<String>,
+    pub owner_channel_name: Option<String>,
+    pub live_broadcast_details: Option<LiveBroadcastDetails>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveBroadcastDetails{
+    pub start_timestamp: Option<String>,
+    pub end_timestamp: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerCaptions{
+    pub player_captions_tracklist_renderer: Option<CaptionTracklist>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptionTracklist{
+    #[serde(default)]
+    pub caption_tracks: Vec<RawCaptionTrack>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawCaptionTrack{
+    pub base_url: String,
+    pub name: Option<Value>,
+    #[serde(default)]
+    pub language_code: String,
+    ///`asr` for auto-generated tracks
+    pub kind: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipConfig{
+    #[serde(default)]
+    pub post_id: String,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub start_time_ms: Option<u64>,
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub end_time_ms: Option<u64>,
+}
+
+///
+/// Finds `<name> = {...}` in the page and parses the object that follows. Handles both
+/// `var ytInitialData = ` and `window["ytInitialData"] = ` assignments.
+///
+pub fn find_json_blob<T: DeserializeOwned>(html: &str, name: &'static str) -> Result<Option<T>, ClypperError>{
+    let mut rest = html;
+    while let Some(index) = rest.find(name){
+        rest = &rest[index + name.len()..];
+        let after_name = rest.trim_start_matches(['"', '\'', ']']).trim_start();
+        let Some(value) = after_name.strip_prefix('=').map(str::trim_start) else{
+            continue;
+        };
+        if !value.starts_with('{'){
+            continue;
+        }
+        //The page keeps going after the object, so only read the first value
+        return match serde_json::Deserializer::from_str(value).into_iter::<T>().next(){
+            Some(Ok(blob)) => Ok(Some(blob)),
+            Some(Err(err)) => Err(ClypperError::InvalidField(name, err.to_string())),
+            None => Ok(None),
+        };
+    }
+    Ok(None)
+}
+
+///
+/// Depth first search for the first value stored under `key`
+///
+pub fn find_key<'v>(value: &'v Value, key: &str) -> Option<&'v Value>{
+    match value{
+        Value::Object(map) => map.get(key).or_else(|| map.values().find_map(|child| find_key(child, key))),
+        Value::Array(values) => values.iter().find_map(|child| find_key(child, key)),
+        _ => None,
+    }
+}
+
+///
+/// Reads youtube's text objects, either `{"simpleText": ...}` or `{"runs": [{"text": ...}]}`
+///
+pub fn text(value: &Value) -> Option<String>{
+    if let Some(text) = value.as_str().or_else(|| value.get("simpleText")?.as_str()){
+        return Some(text.to_string());
+    }
+    let runs = value.get("runs")?.as_array()?;
+    Some(runs.iter().filter_map(|run| run.get("text")?.as_str()).collect())
+}
+
+///
+/// The content of `<meta property="<property>" content="...">`
+///
+fn find_meta(html: &str, property: &str) -> Option<String>{
+    let tag_start = html.find(format!("property=\"{}\"", property).as_str())
+        .and_then(|index| html[..index].rfind('<'))?;
+    let tag = &html[tag_start..];
+    let tag = &tag[..tag.find('>')?];
+    let content = tag.split_once("content=\"")?.1;
+    let content = &content[..content.find('"')?];
+    Some(content
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&"))
+}
+
+///
+/// Youtube sends most numbers as strings, accept either
+///
+fn number_or_string<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Value::deserialize(deserializer)?{
+        Value::Number(number) => number.as_u64(),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    })
+}
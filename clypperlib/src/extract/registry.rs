@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use crate::error::ClypperError;
+
+use super::{
+    captions::{CaptionTrack, Captions},
+    extractor::{Clip, Segment, SiteExtractor},
+    format::FormatSelector,
+    http::{CurlClient, HttpClient},
+    twitch::TwitchExtractor,
+    youtube::YouTubeExtractor,
+};
+
+///
+/// Dispatches clip urls to the first registered [`SiteExtractor`] that matches them
+///
+/// ```no_run
+/// use clypperlib::extract::registry::ExtractorRegistry;
+///
+/// let registry = ExtractorRegistry::with_defaults().unwrap();
+/// let clip = registry.extract("https://www.youtube.com/clip/...").unwrap();
+/// ```
+///
+#[derive(Default)]
+pub struct ExtractorRegistry{
+    extractors: Vec<Box<dyn SiteExtractor>>,
+}
+
+impl ExtractorRegistry{
+    ///
+    /// A registry with no extractors, see [`ExtractorRegistry::with_defaults`] for the built-ins
+    ///
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    ///
+    /// A registry with every built-in extractor, fetching pages with curl
+    ///
+    pub fn with_defaults() -> Result<Self, ClypperError>{
+        Self::with_client(CurlClient::default())
+    }
+
+    ///
+    /// A registry with every built-in extractor, fetching pages through `client`
+    ///
+    pub fn with_client(client: impl HttpClient + 'static) -> Result<Self, ClypperError>{
+        let client: Arc<dyn HttpClient> = Arc::new(client);
+        let mut registry = Self::new();
+        registry.register(YouTubeExtractor::with_client(client.clone())?);
+        registry.register(TwitchExtractor::with_client(client));
+        Ok(registry)
+    }
+
+    ///
+    /// Adds `extractor`, which is tried after everything registered before it
+    ///
+    pub fn register(&mut self, extractor: impl SiteExtractor + 'static) -> &mut Self{
+        self.extractors.push(Box::new(extractor));
+        self
+    }
+
+    pub fn find(&self, url: &str) -> Option<&dyn SiteExtractor>{
+        self.extractors.iter()
+            .find(|extractor| extractor.matches(url))
+            .map(|extractor| extractor.as_ref())
+    }
+
+    pub fn extractors(&self) -> impl Iterator<Item = &dyn SiteExtractor>{
+        self.extractors.iter().map(|extractor| extractor.as_ref())
+    }
+
+    pub fn extract<'url>(&self, url: &'url str) -> Result<Clip<'url>, ClypperError>{
+        self.extract_with(url, &FormatSelector::default())
+    }
+
+    pub fn extract_with<'url>(&self, url: &'url str, selector: &FormatSelector) -> Result<Clip<'url>, ClypperError>{
+        self.find_or_err(url)?.extract_with(url, selector)
+    }
+
+    pub fn extract_segment<'url>(&self, url: &'url str, segment: Segment, selector: &FormatSelector) -> Result<Clip<'url>, ClypperError>{
+        self.find_or_err(url)?.extract_segment(url, segment, selector)
+    }
+
+    ///
+    /// Downloads `track` of the clip at `url`
+    ///
+    pub fn captions(&self, url: &str, track: &CaptionTrack) -> Result<Captions, ClypperError>{
+        self.find_or_err(url)?.captions(track)
+    }
+
+    fn find_or_err(&self, url: &str) -> Result<&dyn SiteExtractor, ClypperError>{
+        self.find(url).ok_or_else(|| ClypperError::UnsupportedUrl(url.to_string()))
+    }
+}
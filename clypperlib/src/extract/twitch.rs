@@ -0,0 +1,259 @@
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::ClypperError;
+
+use super::{
+    extractor::{Clip, ClipTime, SiteExtractor, UrlParts},
+    format::{Format, FormatList, FormatSelector},
+    http::{CurlClient, HttpClient, HttpRequest},
+    metadata::{ClipMetadata, Thumbnail},
+};
+
+const GQL_ENDPOINT: &str = "https://gql.twitch.tv/gql";
+///The client id twitch's own web player uses, GQL rejects requests without one
+const WEB_CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
+const CLIP_QUERY: &str = "query ClypperClip($slug: ID!) {
+  clip(slug: $slug) {
+    id slug title durationSeconds videoOffsetSeconds createdAt viewCount thumbnailURL
+    broadcaster { id login displayName }
+    curator { id login displayName }
+    video { id title lengthSeconds }
+    videoQualities { quality frameRate sourceURL }
+    playbackAccessToken(params: {platform: \"web\", playerBackend: \"mediaplayer\", playerType: \"site\"}) { signature value }
+  }
+}";
+
+///
+/// Extracts twitch clips through twitch's GQL api. Clip media on twitch is already cut, so the
+/// resulting [`Clip`] spans the whole file and [`Clip::source_time`] holds where it sits in the VOD.
+///
+pub struct TwitchExtractor<H = CurlClient>{
+    client: H,
+    endpoint: String,
+    client_id: String,
+}
+
+impl TwitchExtractor<CurlClient>{
+    pub fn new() -> Self{
+        Self::default()
+    }
+}
+
+impl Default for TwitchExtractor<CurlClient>{
+    fn default() -> Self{
+        Self::with_client(CurlClient::default())
+    }
+}
+
+impl<H: HttpClient> TwitchExtractor<H>{
+    pub fn with_client(client: H) -> Self{
+        Self{
+            client,
+            endpoint: GQL_ENDPOINT.to_string(),
+            client_id: WEB_CLIENT_ID.to_string(),
+        }
+    }
+
+    ///
+    /// Sends GQL queries to `endpoint` instead of twitch, e.g. a local server replaying recorded responses
+    ///
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self{
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self{
+        self.client_id = client_id.into();
+        self
+    }
+
+    ///
+    /// The clip slug from either `clips.twitch.tv/<slug>` or `twitch.tv/<channel>/clip/<slug>`
+    ///
+    pub fn slug(url: &str) -> Option<&str>{
+        let url = UrlParts::parse(url)?;
+        let segments: Vec<&str> = url.segments().collect();
+        match segments.as_slice(){
+            [slug] if url.is_host("clips.twitch.tv") => Some(slug),
+            [_, "clip", slug, ..] if url.is_host("twitch.tv") => Some(slug),
+            _ => None,
+        }
+    }
+
+    fn query_clip(&self, slug: &str) -> Result<GqlClip, ClypperError>{
+        let body = json!({
+            "query": CLIP_QUERY,
+            "variables": { "slug": slug },
+        });
+        let request = HttpRequest::post(self.endpoint.as_str(), body.to_string())
+            .header("Client-ID", self.client_id.as_str())
+            .header("Content-Type", "application/json");
+        let response = self.client.fetch(&request)?;
+        let response: GqlResponse = serde_json::from_slice(&response.body)
+            .map_err(|err| ClypperError::InvalidField("data", err.to_string()))?;
+        if let Some(error) = response.errors.first(){
+            return Err(ClypperError::Network(format!("twitch GQL error: {}", error.message)));
+        }
+        response.data
+            .and_then(|data| data.clip)
+            .ok_or_else(|| ClypperError::ClipUnavailable(format!("twitch clip {} does not exist", slug)))
+    }
+}
+
+impl<H: HttpClient> SiteExtractor for TwitchExtractor<H>{
+    fn name(&self) -> &'static str{
+        "twitch"
+    }
+
+    fn matches(&self, url: &str) -> bool{
+        Self::slug(url).is_some()
+    }
+
+    fn extract_with<'url>(&self, url: &'url str, selector: &FormatSelector) -> Result<Clip<'url>, ClypperError>{
+        let slug = Self::slug(url).ok_or_else(|| ClypperError::UnsupportedUrl(url.to_string()))?;
+        let clip = self.query_clip(slug)?;
+        let token = clip.playback_access_token.as_ref().ok_or(ClypperError::MissingField("playbackAccessToken"))?;
+        let formats = FormatList(clip.video_qualities.iter().filter_map(|quality| quality.to_format(token)).collect());
+        if formats.is_empty(){
+            return Err(ClypperError::MissingField("videoQualities"));
+        }
+        let resource = formats.resource(selector)?;
+
+        let duration_ms = clip.duration_seconds.ok_or(ClypperError::MissingField("durationSeconds"))? * 1000;
+        let source_time = clip.video_offset_seconds.map(|offset| ClipTime(offset * 1000, offset * 1000 + duration_ms));
+        Ok(Clip{
+            url,
+            resource,
+            time: ClipTime(0, duration_ms),
+            source_time,
+            formats,
+            metadata: clip.metadata(),
+            captions: vec![],
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GqlResponse{
+    data: Option<GqlData>,
+    #[serde(default)]
+    errors: Vec<GqlError>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GqlData{
+    clip: Option<GqlClip>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GqlError{
+    message: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlClip{
+    title: Option<String>,
+    duration_seconds: Option<u64>,
+    video_offset_seconds: Option<u64>,
+    #[serde(default)]
+    video_qualities: Vec<GqlVideoQuality>,
+    playback_access_token: Option<GqlAccessToken>,
+    created_at: Option<String>,
+    view_count: Option<u64>,
+    #[serde(rename = "thumbnailURL")]
+    thumbnail_url: Option<String>,
+    broadcaster: Option<GqlUser>,
+    curator: Option<GqlUser>,
+    video: Option<GqlVideo>,
+}
+
+impl GqlClip{
+    ///
+    /// `video` is null once the VOD has been deleted, leaving only the clip's own details
+    ///
+    fn metadata(&self) -> ClipMetadata{
+        ClipMetadata{
+            title: self.title.clone(),
+            creator: self.curator.as_ref().map(GqlUser::name),
+            source_video_id: self.video.as_ref().map(|video| video.id.clone()),
+            source_title: self.video.as_ref().and_then(|video| video.title.clone()),
+            channel_name: self.broadcaster.as_ref().map(GqlUser::name),
+            channel_id: self.broadcaster.as_ref().map(|broadcaster| broadcaster.id.clone()),
+            date: self.created_at.clone(),
+            source_duration_ms: self.video.as_ref().and_then(|video| video.length_seconds).map(|length| length * 1000),
+            view_count: self.view_count,
+            thumbnails: self.thumbnail_url.iter().map(|url| Thumbnail{
+                url: url.clone(),
+                ..Thumbnail::default()
+            }).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlUser{
+    id: String,
+    login: String,
+    display_name: Option<String>,
+}
+
+impl GqlUser{
+    fn name(&self) -> String{
+        self.display_name.clone().unwrap_or_else(|| self.login.clone())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlVideo{
+    id: String,
+    title: Option<String>,
+    length_seconds: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlVideoQuality{
+    quality: String,
+    frame_rate: Option<f64>,
+    #[serde(rename = "sourceURL")]
+    source_url: String,
+}
+
+impl GqlVideoQuality{
+    ///
+    /// Twitch clip qualities are muxed mp4s. The quality (e.g. `720`) doubles as the itag.
+    ///
+    fn to_format(&self, token: &GqlAccessToken) -> Option<Format>{
+        let height: u32 = self.quality.parse().ok()?;
+        let separator = if self.source_url.contains('?') { '&' } else { '?' };
+        Some(Format{
+            itag: height,
+            url: format!("{}{}sig={}&token={}", self.source_url, separator, token.signature, percent_encode(token.value.as_str())),
+            container: "mp4".to_string(),
+            codec: "avc1, mp4a".to_string(),
+            //Only the height is given, clips aren't always 16:9
+            width: None,
+            height: Some(height),
+            fps: self.frame_rate.map(|fps| fps.round() as u32),
+            audio_channels: Some(2),
+            ..Format::default()
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GqlAccessToken{
+    signature: String,
+    value: String,
+}
+
+fn percent_encode(value: &str) -> String{
+    value.bytes().map(|byte| match byte{
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
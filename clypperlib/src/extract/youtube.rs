@@ -0,0 +1,150 @@
+use indicatif::{ProgressStyle, ProgressBar};
+
+use crate::error::ClypperError;
+
+use super::{
+    captions::{CaptionTrack, Captions},
+    extractor::{parse_timestamp, Clip, ClipResource, ClipTime, Segment, SiteExtractor, UrlParts},
+    format::{FormatList, FormatSelector},
+    http::{CurlClient, HttpClient},
+    player::PlayerPage,
+};
+
+///
+/// Extracts youtube clips. Generic over the [`HttpClient`] pages are fetched with so it can
+/// run against saved pages or a local server instead of youtube.
+///
+pub struct YouTubeExtractor<H = CurlClient>{
+    client: H,
+
+    spinner: ProgressBar,
+}
+
+impl YouTubeExtractor<CurlClient>{
+    pub fn new() -> Result<Self, ClypperError>{
+        Self::with_client(CurlClient::default())
+    }
+}
+
+impl<H: HttpClient> YouTubeExtractor<H>{
+    pub fn with_client(client: H) -> Result<Self, ClypperError>{
+        let style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}").unwrap();
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_style(style);
+
+        Ok(Self{
+            client,
+            spinner,
+        })
+    }
+
+    pub fn client(&self) -> &H{
+        &self.client
+    }
+
+    fn parse_url(url: &str) -> Option<YouTubeUrl<'_>>{
+        let parts = UrlParts::parse(url)?;
+        let segments: Vec<&str> = parts.segments().collect();
+        let id = if parts.is_host("youtu.be"){
+            segments.first().copied()
+        }else if parts.is_host("youtube.com"){
+            match segments.as_slice(){
+                ["clip", _, ..] => return Some(YouTubeUrl::Clip),
+                ["watch"] => parts.query_param("v"),
+                ["live", id, ..] => Some(*id),
+                _ => None,
+            }
+        }else{
+            None
+        }?;
+        Some(YouTubeUrl::Video{
+            id,
+            start_ms: parts.query_param("t").and_then(parse_timestamp),
+        })
+    }
+
+    ///
+    /// Fetches and parses the page at `url` and picks the streams to download from it
+    ///
+    fn load_page(&self, url: &str, selector: &FormatSelector) -> Result<(PlayerPage, FormatList, ClipResource), ClypperError>{
+        self.spinner.set_message("Getting clip info...");
+        let html = self.client.get_text(url)?;
+        let page = PlayerPage::parse(html.as_str())?;
+        page.check_playable()?;
+
+        let formats = FormatList(page.formats());
+        if formats.is_empty(){
+            return Err(ClypperError::MissingField("streamingData"));
+        }
+        let resource = formats.resource(selector)?;
+        Ok((page, formats, resource))
+    }
+
+    fn clip_time(page: &PlayerPage) -> Result<ClipTime, ClypperError>{
+        let clip_config = page.clip_config().ok_or(ClypperError::MissingField("clipConfig"))?;
+        let start_ms = clip_config.start_time_ms.ok_or(ClypperError::MissingField("startTimeMs"))?;
+        let end_ms = clip_config.end_time_ms.ok_or(ClypperError::MissingField("endTimeMs"))?;
+        Ok(ClipTime(start_ms, end_ms))
+    }
+}
+
+enum YouTubeUrl<'url>{
+    ///A `/clip/` page, which says which part of the video it covers
+    Clip,
+    ///Any other way of linking a video: `watch?v=`, `youtu.be/` or `/live/`
+    Video{
+        id: &'url str,
+        start_ms: Option<u64>,
+    },
+}
+
+impl<H: HttpClient> SiteExtractor for YouTubeExtractor<H>{
+    fn name(&self) -> &'static str{
+        "youtube"
+    }
+
+    fn matches(&self, url: &str) -> bool{
+        Self::parse_url(url).is_some()
+    }
+
+    fn extract_with<'url>(&self, url: &'url str, selector: &FormatSelector) -> Result<Clip<'url>, ClypperError>{
+        if let Some(YouTubeUrl::Video{ .. }) = Self::parse_url(url){
+            return Err(ClypperError::Unsupported(format!("{} is not a clip, pass a segment to cut from it", url)));
+        }
+        let (page, formats, resource) = self.load_page(url, selector)?;
+        let time = Self::clip_time(&page)?;
+        let metadata = page.metadata(true);
+        self.spinner.finish_with_message("Getting clip info... Done!");
+        Ok(Clip { url, resource, time, source_time: Some(time), formats, metadata, captions: page.caption_tracks() })
+    }
+
+    fn extract_segment<'url>(&self, url: &'url str, segment: Segment, selector: &FormatSelector) -> Result<Clip<'url>, ClypperError>{
+        let youtube_url = Self::parse_url(url).ok_or_else(|| ClypperError::UnsupportedUrl(url.to_string()))?;
+        let (page, formats, resource) = match youtube_url{
+            YouTubeUrl::Clip => self.load_page(url, selector)?,
+            YouTubeUrl::Video{ id, .. } => self.load_page(format!("https://www.youtube.com/watch?v={}", id).as_str(), selector)?,
+        };
+        let start_ms = match youtube_url{
+            YouTubeUrl::Clip => Self::clip_time(&page)?.0,
+            YouTubeUrl::Video{ start_ms, .. } => start_ms.unwrap_or(0),
+        };
+        let time = segment.time(start_ms);
+        if time.1 <= time.0{
+            return Err(ClypperError::InvalidField("segment", format!("{}ms-{}ms", time.0, time.1)));
+        }
+        //Streams still live report a length of 0
+        let metadata = page.metadata(matches!(youtube_url, YouTubeUrl::Clip));
+        if let Some(length_ms) = metadata.source_duration_ms{
+            if time.1 > length_ms{
+                return Err(ClypperError::InvalidField("segment", format!("{}ms-{}ms is past the end of the {}ms video", time.0, time.1, length_ms)));
+            }
+        }
+        self.spinner.finish_with_message("Getting clip info... Done!");
+        Ok(Clip { url, resource, time, source_time: Some(time), formats, metadata, captions: page.caption_tracks() })
+    }
+
+    fn captions(&self, track: &CaptionTrack) -> Result<Captions, ClypperError>{
+        let json = self.client.get_text(format!("{}&fmt=json3", track.url).as_str())?;
+        Captions::from_json3(json.as_str())
+    }
+}
@@ -1,36 +1,937 @@
-use std::{error::Error, fmt::Write, cmp::{min, max}};
+//!
+//! Clypperlib downloads youtube clips by extracting the stream urls from the clip page and
+//! cutting the clip out of them with ffmpeg.
+//!
+//! Most tools only need the [`Clypper`] facade and a [`ClipRequest`]. The building blocks are
+//! available through the module tree:
+//!
+//! - [`extract`] turns a clip url into a [`Clip`] (stream urls and start/end times), with one
+//!   [`SiteExtractor`] per supported site collected in an [`ExtractorRegistry`]
+//! - [`download`] drives ffmpeg to encode a [`Clip`] into an output file
+//! - [`error`] holds [`ClypperError`], returned by everything that can fail
+//!
+#[cfg(test)]
+use indicatif::{ProgressBar, ProgressStyle};
+#[cfg(test)]
+use extract::http::{FixtureClient, FixtureServer};
 
-use download::{downloader::Downloader, ffmpeg::{FFmpegError, FFmpeg}};
-use extract::extractor::{ClypperError, ClipTime};
-use indicatif::{ProgressBar, ProgressStyle, ProgressState};
+pub mod extract;
+pub mod download;
+pub mod error;
+mod clypper;
 
-use crate::{extract::extractor::{Extractor, ClipResource}, download::ffmpeg::FFmpegState};
+pub use clypper::{Clypper, ClipRequest, ClipJob};
+pub use error::{ClypperError, FFmpegFailure};
+pub use extract::{
+    captions::{CaptionTrack, Captions},
+    extractor::{Clip, ClipResource, ClipTime, Segment, SiteExtractor},
+    format::{Format, FormatList, FormatSelector},
+    metadata::{ClipMetadata, Thumbnail},
+    registry::ExtractorRegistry,
+    youtube::YouTubeExtractor,
+};
+pub use download::{
+    cut::CutMode,
+    downloader::Downloader,
+    ffmpeg::{FFmpeg, FFmpegControl, FFmpegJob, FFmpegOutput, FFmpegState},
+    filter::{Filter, FilterGraph},
+    installation::FFmpegInstallation,
+    layout::{LayoutStore, LayoutTemplate},
+    loudness::{LoudnessReport, LoudnessTarget},
+    profile::{Container, EncodeProfile, EncodeProfiles},
+    progress::ProgressReport,
+    reframe::{AspectRatio, Reframe, ReframeMode},
+    subtitles::{CaptionStyle, Color, Karaoke, SubtitleFormat},
+    verify::{Verification, VerificationReport},
+};
 
-mod extract;
-mod download;
+#[cfg(test)]
+const CLIP_URL: &str = "https://www.youtube.com/clip/UgkxFixtureClip0000000000000000000";
+#[cfg(test)]
+const CLIP_PAGE: &str = include_str!("../fixtures/youtube_clip.html");
+
+///
+/// A scratch directory for test outputs, emptied on every run
+///
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf{
+    let dir = std::env::temp_dir().join(format!("clypper-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+///
+/// Generates a video and an audio stream with ffmpeg, serves them locally and points a copy of
+/// the fixture clip page at them
+///
+#[cfg(test)]
+fn fixture_media(dir: &std::path::Path) -> Result<(FixtureServer, YouTubeExtractor<FixtureClient>), ClypperError>{
+    let generate = |source: &str, codec: &[&str], file: &str| -> Result<(), ClypperError>{
+        let status = std::process::Command::new(download::installation::default_ffmpeg())
+            .args(["-hide_banner", "-loglevel", "error", "-y", "-f", "lavfi", "-i", source, "-c"])
+            .args(codec)
+            .arg(dir.join(file))
+            .status()
+            .map_err(ClypperError::FFmpegSpawn)?;
+        if !status.success(){
+            return Err(ClypperError::FFmpegExit{ status: status.code(), failure: FFmpegFailure::Unknown, stderr_tail: vec![] });
+        }
+        Ok(())
+    };
+    //A keyframe every 2s, so the 10s clip has some to smart cut between
+    generate("testsrc=duration=20:size=320x180:rate=30", &["libx264", "-g", "60"], "137.mp4")?;
+    generate("sine=frequency=440:duration=20", &["aac"], "140.m4a")?;
+
+    let server = FixtureServer::start()?;
+    server.file("/videoplayback/137.mp4", dir.join("137.mp4"))?;
+    server.file("/videoplayback/140.m4a", dir.join("140.m4a"))?;
+    let expire = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() + 6 * 60 * 60;
+    let page = CLIP_PAGE
+        .replace("https://rr1---sn-fixture.googlevideo.com", server.url("").as_str())
+        .replace("expire=1700000000", format!("expire={}", expire).as_str());
+    let extractor = YouTubeExtractor::with_client(FixtureClient::new().page(CLIP_URL, page))?;
+    Ok((server, extractor))
+}
 
 #[test]
 fn test_extractor() -> Result<(), ClypperError> {
-    let url = "https://www.youtube.com/clip/UgkxWw82JHM2Y6ZFPbT9lIVAgOzgbWEl-ocz";
-    let extractor = Extractor::new().unwrap();
-    let clip = match extractor.extract(url){
+    let extractor = YouTubeExtractor::with_client(FixtureClient::new().page(CLIP_URL, CLIP_PAGE))?;
+    let clip = match extractor.extract(CLIP_URL){
         Ok(clip) => clip,
         Err(err) => {
             println!("{:?}", err);
             return Err(err);
         }
     };
-    let ClipResource(_vid_url, _aud_url) = clip.resource;
-    let ClipTime(_start, _end) = clip.time;
+    assert_eq!(clip.resource.expires_at(), Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)));
+    assert!(clip.resource.is_expired());
+    let ClipResource(vid_url, aud_url) = clip.resource;
+    let ClipTime(start, end) = clip.time;
+    assert!(vid_url.contains("itag=137"));
+    assert!(aud_url.contains("itag=140"));
+    assert_eq!((start, end), (5000, 15000));
+    assert_eq!(clip.formats.iter().count(), 6);
+
+    let metadata = clip.metadata;
+    assert_eq!(metadata.title.as_deref(), Some("the jump nobody believed"));
+    assert_eq!(metadata.creator.as_deref(), Some("ClipFan"));
+    assert_eq!(metadata.source_video_id.as_deref(), Some("fXtUZmQ8dYc"));
+    assert_eq!(metadata.channel_name.as_deref(), Some("Fixture Streamer"));
+    assert_eq!(metadata.date.as_deref(), Some("2023-11-13T19:02:11+00:00"));
+    assert_eq!(metadata.source_duration_ms, Some(7_263_000));
+    assert_eq!(metadata.view_count, Some(48213));
+    assert_eq!(metadata.best_thumbnail().map(|thumbnail| thumbnail.url.as_str()), Some("https://i.ytimg.com/vi/fXtUZmQ8dYc/maxresdefault.jpg"));
+    assert_eq!(metadata.file_name("{date} {channel} - {source_title}"), "2023-11-13 Fixture Streamer - Speedrunning the tutorial _blind_ & failing");
+    let braces = ClipMetadata{ title: Some("{channel} {date} {nope}".to_string()), ..metadata.clone() };
+    assert_eq!(braces.file_name("{title} {"), "{channel} {date} {nope} {");
+    let dots = ClipMetadata{ title: Some("..".to_string()), ..metadata.clone() };
+    assert_eq!(dots.file_name("{title}"), "fXtUZmQ8dYc");
+    assert_eq!(ClipMetadata{ source_video_id: None, ..dots }.file_name("{title}"), "clip");
+
+    assert!(matches!(extractor.extract("https://www.youtube.com/clip/missing"), Err(ClypperError::HttpStatus(_, 404))));
+
+    let registry = ExtractorRegistry::with_client(FixtureClient::new().page(CLIP_URL, CLIP_PAGE))?;
+    assert_eq!(registry.find(CLIP_URL).map(|extractor| extractor.name()), Some("youtube"));
+    assert!(matches!(registry.extract("https://example.com/clip/abc"), Err(ClypperError::UnsupportedUrl(_))));
+    Ok(())
+}
+
+#[test]
+fn test_segments() -> Result<(), ClypperError>{
+    use extract::extractor::parse_timestamp;
+
+    assert_eq!(parse_timestamp("90"), Some(90_000));
+    assert_eq!(parse_timestamp("1m30s"), Some(90_000));
+    assert_eq!(parse_timestamp("1h2m3s"), Some(3_723_000));
+    assert_eq!(parse_timestamp("1:02:03.5"), Some(3_723_500));
+    assert_eq!(parse_timestamp("1m30"), None);
+    assert_eq!(parse_timestamp("soon"), None);
+
+    let client = FixtureClient::new()
+        .page(CLIP_URL, CLIP_PAGE)
+        .page("https://www.youtube.com/watch?v=fXtUZmQ8dYc", CLIP_PAGE);
+    let registry = ExtractorRegistry::with_client(client)?;
+    let selector = FormatSelector::default();
+
+    let clip = registry.extract_segment("https://youtu.be/fXtUZmQ8dYc?t=1m30s", Segment::Duration(20_000), &selector)?;
+    assert_eq!(clip.time, ClipTime(90_000, 110_000));
+    assert_eq!((clip.metadata.title, clip.metadata.source_title.is_some()), (None, true));
+    let clip = registry.extract_segment("https://www.youtube.com/live/fXtUZmQ8dYc?feature=share", Segment::Range(ClipTime(60_000, 75_000)), &selector)?;
+    assert_eq!(clip.time, ClipTime(60_000, 75_000));
+    let clip = registry.extract_segment(CLIP_URL, Segment::Duration(3_000), &selector)?;
+    assert_eq!(clip.time, ClipTime(5_000, 8_000));
+
+    assert!(matches!(registry.extract("https://www.youtube.com/watch?v=fXtUZmQ8dYc"), Err(ClypperError::Unsupported(_))));
+    let past_end = Segment::Range(ClipTime(7_260_000, 7_270_000));
+    assert!(matches!(registry.extract_segment("https://www.youtube.com/watch?v=fXtUZmQ8dYc", past_end, &selector), Err(ClypperError::InvalidField("segment", _))));
+    Ok(())
+}
+
+#[test]
+fn test_stream_expiry() -> Result<(), ClypperError>{
+    use extract::extractor::{is_url_expired, url_expiry};
+
+    let expired = "https://rr1---sn-fixture.googlevideo.com/videoplayback?expire=1700000000&itag=137";
+    let manifest = "https://manifest.googlevideo.com/api/manifest/hls_playlist/expire/1700000000/ei/abc/index.m3u8";
+    assert_eq!(url_expiry(manifest), url_expiry(expired));
+    assert!(is_url_expired(expired));
+    assert!(!is_url_expired("https://production.assets.clips.twitchcdn.net/v2/media/fixture/720.mp4"));
+
+    //Expired inputs are caught before ffmpeg is ever started
+    let mut ffmpeg = FFmpeg::new();
+    ffmpeg.time(0, 1000)?.input(expired)?.input(expired)?.output("never.mp4")?;
+    let job = ffmpeg.spawn()?;
+    assert!(matches!(job.wait(), Err(ClypperError::StreamExpired(url)) if url == expired));
+
+    let refreshes = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = refreshes.clone();
+    let mut ffmpeg = FFmpeg::new();
+    ffmpeg.time(0, 1000)?.input(expired)?.input(expired)?.output("never.mp4")?
+        .refresh_inputs(move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(vec![expired.to_string(), expired.to_string()])
+        });
+    let job = ffmpeg.spawn()?;
+    assert!(matches!(job.wait(), Err(ClypperError::StreamExpired(_))));
+    assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[test]
+fn test_ffmpeg_control() -> Result<(), ClypperError>{
+    let states = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let seen = states.clone();
+    let mut ffmpeg = FFmpeg::new();
+    ffmpeg.time(0, 1000)?.input("http://127.0.0.1:9/video.mp4")?.input("http://127.0.0.1:9/audio.m4a")?
+        .state_change_callback(move |state| seen.lock().unwrap().push(state));
+    assert!(matches!(ffmpeg.spawn(), Err(ClypperError::FFmpeg(_))));
+
+    //Jobs can be controlled before they start, a cancelled one never runs ffmpeg
+    let control = ffmpeg.control();
+    control.pause()?;
+    assert_eq!(control.status(), FFmpegState::Paused);
+    control.resume()?;
+    assert_eq!(control.status(), FFmpegState::NotStarted);
+    control.cancel()?;
+    ffmpeg.output("never.mp4")?;
+    let job = ffmpeg.spawn()?;
+    assert!(matches!(job.wait(), Err(ClypperError::Cancelled)));
+    assert_eq!(control.status(), FFmpegState::Cancelled);
+    assert_eq!(*states.lock().unwrap(), [FFmpegState::Paused, FFmpegState::NotStarted, FFmpegState::Cancelled]);
+    Ok(())
+}
+
+#[test]
+fn test_ffmpeg_passes() -> Result<(), ClypperError>{
+    let (video, audio) = ("https://example.com/video", "https://example.com/audio");
+    let contains = |args: &[String], expected: &[&str]| args.windows(expected.len()).any(|window| window.iter().zip(expected).all(|(arg, expected)| arg == expected));
+    let loudnorm = "[1:a]loudnorm=I=-14:TP=-1:LRA=11:print_format=json,aresample=48000[loudnorm]";
+
+    let mut ffmpeg = FFmpeg::new();
+    ffmpeg.time(5_000, 15_000)?.input(video)?.input(audio)?.output("clip.mp4")?
+        .metadata("title", "gg")
+        .normalize_loudness(LoudnessTarget::STREAMING);
+    let runner = ffmpeg.prepare()?;
+    //Measuring only reads the audio
+    assert_eq!(runner.measure_pass(LoudnessTarget::STREAMING).args(), [
+        "-hide_banner", "-nostats", "-progress", "pipe:2", "-y",
+        "-ss", "5000ms", "-to", "15000ms", "-i", audio,
+        "-map", "0:a", "-af", "loudnorm=I=-14:TP=-1:LRA=11:print_format=json", "-f", "null", "-",
+    ]);
+    let args = runner.reencode_pass().args();
+    assert!(contains(&args, &["-ss", "5000ms", "-to", "15000ms", "-i", video, "-ss", "5000ms", "-to", "15000ms", "-i", audio]));
+    assert!(contains(&args, &["-filter_complex", loudnorm, "-map", "0:v", "-map", "[loudnorm]"]));
+    assert!(contains(&args, &["-metadata", "title=gg"]));
+    assert_eq!(args.last().map(String::as_str), Some("clip.mp4"));
+    //Smart cuts copy the middle of the video and encode the audio when joining the parts
+    let args = runner.video_part_pass(2_002_000, 6_006_000, true, "parts/1.ts".into()).args();
+    assert!(contains(&args, &["-ss", "2002000us", "-to", "6006000us", "-i", video, "-map", "0:v", "-an", "-c:v", "copy", "parts/1.ts"]));
+    let args = runner.join_pass(std::path::Path::new("parts/list.txt")).args();
+    assert!(contains(&args, &["-f", "concat", "-safe", "0", "-i", "parts/list.txt", "-ss", "5000ms", "-to", "15000ms", "-i", audio]));
+    assert!(contains(&args, &["-filter_complex", loudnorm, "-map", "0:v", "-map", "[loudnorm]", "-c:v", "copy"]));
+
+    let mut ffmpeg = FFmpeg::new();
+    ffmpeg.time(5_000, 15_000)?.input(video)?.input(audio)?.output("clip.mp4")?.cut_mode(CutMode::StreamCopy);
+    let args = ffmpeg.prepare()?.stream_copy_pass().args();
+    assert!(contains(&args, &["-map", "0:v", "-map", "1:a", "-c", "copy", "-avoid_negative_ts", "make_zero"]));
+    assert!(!args.iter().any(|arg| arg == "-filter_complex"));
+    Ok(())
+}
+
+#[test]
+fn test_progress_parser(){
+    use download::progress::ProgressParser;
+
+    let mut parser = ProgressParser::new(10_000);
+    let output = "[https @ 0x5581] Opening 'https://rr1.googlevideo.com/videoplayback' for reading
+frame=150
+fps=49.87
+stream_0_0_q=28.0
+bitrate= 512.3kbits/s
+total_size=262144
+out_time_us=5000000
+out_time_ms=5000000
+out_time=00:00:05.000000
+dup_frames=0
+drop_frames=0
+speed=2.5x
+progress=continue
+frame=300
+fps=N/A
+bitrate=N/A
+total_size=524288
+out_time_us=10000000
+speed=2.49x
+progress=end";
+    let reports: Vec<ProgressReport> = output.lines().filter_map(|line| parser.feed(line)).collect();
+    assert_eq!(reports.len(), 2);
+    let report = &reports[0];
+    assert_eq!((report.frame, report.fps, report.bitrate_kbps), (Some(150), Some(49.87), Some(512.3)));
+    assert_eq!((report.total_size, report.out_time_ms, report.speed), (Some(262144), 5_000, Some(2.5)));
+    assert_eq!((report.end, report.percent), (false, Some(50.0)));
+    assert_eq!(report.eta, Some(std::time::Duration::from_secs(2)));
+    let report = &reports[1];
+    assert_eq!((report.fps, report.bitrate_kbps), (None, None));
+    assert_eq!((report.end, report.percent, report.eta), (true, Some(100.0), Some(std::time::Duration::ZERO)));
+}
+
+#[test]
+fn test_reframe() -> Result<(), ClypperError>{
+    use download::reframe::PanKeyframe;
+
+    let center = Reframe::new(AspectRatio::Vertical, ReframeMode::Center);
+    assert_eq!(
+        center.filter_graph("0:v", "v").render()?,
+        "[0:v]crop=w=min(iw\\,ih*9/16):h=min(ih\\,iw*16/9):x=(iw-ow)/2:y=(ih-oh)/2,scale=w=1080:h=1920,setsar=1[v]",
+    );
+    let square = Reframe::new(AspectRatio::Square, ReframeMode::Offset(1.5)).with_size(720, 720);
+    assert_eq!(
+        square.filter_graph("0:v", "v").render()?,
+        "[0:v]crop=w=min(iw\\,ih*1/1):h=min(ih\\,iw*1/1):x=(iw-ow)*1:y=(ih-oh)/2,scale=w=720:h=720,setsar=1[v]",
+    );
+    assert_eq!(AspectRatio::Portrait.default_size(), (1080, 1350));
+
+    let pan = Reframe::new(AspectRatio::Vertical, ReframeMode::Pan(vec![
+        PanKeyframe{ time_ms: 4_000, offset: 1.0 },
+        PanKeyframe{ time_ms: 2_000, offset: 0.0 },
+    ]));
+    assert!(pan.filter_graph("0:v", "v").render()?.contains("x=(iw-ow)*(if(lt(t\\,2)\\,0\\,if(lt(t\\,4)\\,0+(1)*(t-2)/2\\,1)))"));
+    let still = Reframe::new(AspectRatio::Vertical, ReframeMode::Pan(vec![]));
+    assert!(still.filter_graph("0:v", "v").render()?.contains("x=(iw-ow)*(0.5):"));
+
+    let blur = Reframe::new(AspectRatio::Vertical, ReframeMode::BlurPad);
+    let graph = blur.filter_graph("0:v", "v");
+    assert!(graph.filter_names().contains(&"boxblur"));
+    let graph = graph.render()?;
+    assert!(graph.starts_with("[0:v]split=2[bg][fg];[bg]scale=w=1080:h=1920:force_original_aspect_ratio=increase,crop=w=1080:h=1920:x=(iw-ow)/2:y=(ih-oh)/2,boxblur=luma_radius=20:luma_power=5[blurred]"));
+    assert!(graph.ends_with("[blurred][fitted]overlay=x=(W-w)/2:y=(H-h)/2,setsar=1[v]"));
+
+    let mut ffmpeg = FFmpeg::new();
+    let result = ffmpeg.input("https://example.com/video").unwrap()
+        .output("clip.mp4").unwrap()
+        .cut_mode(CutMode::StreamCopy)
+        .reframe(center)
+        .spawn();
+    assert!(matches!(result, Err(ClypperError::FFmpegUnsupported(_))));
+    Ok(())
+}
+
+#[test]
+fn test_layout() -> Result<(), ClypperError>{
+    use download::layout::{Rect, Region, SourceRect};
+
+    let stacked = LayoutTemplate::stacked("facecam top", SourceRect::new(0.0, 0.0, 0.25, 0.25), SourceRect::new(0.25, 0.0, 0.5, 1.0));
+    stacked.validate()?;
+    assert_eq!(stacked.filter_graph("0:v", "v").render()?, [
+        "[0:v]split=3[s0][s1][s2]",
+        "[s0]scale=w=1080:h=1920,setsar=1,drawbox=x=0:y=0:w=iw:h=ih:color=black:t=fill[base0]",
+        "[s1]crop=w=iw*0.25:h=ih*0.25:x=iw*0:y=ih*0,scale=w=1080:h=640:force_original_aspect_ratio=increase,crop=w=1080:h=640:x=(iw-ow)/2:y=(ih-oh)/2,setsar=1[r0]",
+        "[base0][r0]overlay=x=0:y=0[base1]",
+        "[s2]crop=w=iw*0.5:h=ih*1:x=iw*0.25:y=ih*0,scale=w=1080:h=1280:force_original_aspect_ratio=increase,crop=w=1080:h=1280:x=(iw-ow)/2:y=(ih-oh)/2,setsar=1[r1]",
+        "[base1][r1]overlay=x=0:y=640[v]",
+    ].join(";"));
+
+    let outside = LayoutTemplate::new("broken", vec![
+        Region{ name: "gameplay".to_string(), source: SourceRect::full(), destination: Rect::new(0, 1000, 1080, 1280) },
+    ]);
+    assert!(matches!(outside.validate(), Err(ClypperError::InvalidField("layout", _))));
+
+    let mut store = LayoutStore::new();
+    store.set("UCfixturechannel0000000", stacked.clone())?;
+    assert!(store.set("UCother", outside).is_err());
+    let json = serde_json::to_string(&store).map_err(|err| ClypperError::InvalidField("layouts", err.to_string()))?;
+    let store = LayoutStore::from_json(json.as_str())?;
+    let metadata = ClipMetadata{ channel_id: Some("UCfixturechannel0000000".to_string()), ..ClipMetadata::default() };
+    assert_eq!(store.for_clip(&metadata), Some(&stacked));
+    assert_eq!(store.for_clip(&ClipMetadata::default()), None);
+    let overflowing = r#"{"channels": {"UCother": {"name": "overflow", "regions": [
+        {"name": "gameplay", "source": {"x": 0, "y": 0, "width": 1, "height": 1}, "destination": {"x": 4294967295, "y": 0, "width": 1080, "height": 1920}}
+    ]}}}"#;
+    assert!(matches!(LayoutStore::from_json(overflowing), Err(ClypperError::InvalidField("layout", _))));
+
+    let mut ffmpeg = FFmpeg::new();
+    let result = ffmpeg.input("https://example.com/video")?
+        .output("clip.mp4")?
+        .layout(stacked)
+        .reframe(Reframe::default())
+        .spawn();
+    assert!(matches!(result, Err(ClypperError::FFmpeg(_))));
+    Ok(())
+}
+
+#[test]
+fn test_filter_graph() -> Result<(), ClypperError>{
+    use download::filter::StreamType;
+
+    let mut graph = FilterGraph::new();
+    graph.chain(&["0:v"], vec![Filter::scale(1280, 720), Filter::Fps("30".to_string())], &["v"])
+        .chain(&["1:a"], vec![Filter::ATempo(1.25), Filter::Volume("-3dB".to_string())], &["a"]);
+    assert_eq!(graph.validate()?, [("v".to_string(), StreamType::Video), ("a".to_string(), StreamType::Audio)]);
+    assert_eq!(graph.render()?, "[0:v]scale=w=1280:h=720,fps=30[v];[1:a]atempo=1.25,volume=-3dB[a]");
+    assert_eq!(graph.filter_names(), ["atempo", "fps", "scale", "volume"]);
+
+    let text = Filter::DrawText{
+        text: "it's 3:00, go 100%".to_string(),
+        x: "(w-text_w)/2".to_string(),
+        y: "h-100".to_string(),
+        font_size: 48,
+        color: "white".to_string(),
+        font_file: None,
+        box_color: None,
+    };
+    let mut graph = FilterGraph::new();
+    graph.chain(&["0:v"], vec![text], &["v"]);
+    //`%` escaped for drawtext's text expansion, then once for its options and again for the graph around them
+    assert_eq!(graph.render()?, "[0:v]drawtext=text=it\\\\\\'s 3\\\\:00\\, go 100\\\\\\\\%:x=(w-text_w)/2:y=h-100:fontsize=48:fontcolor=white[v]");
+
+    let invalid = |graph: &FilterGraph| matches!(graph.validate(), Err(ClypperError::FilterGraph(_)));
+    let mut unknown = FilterGraph::new();
+    unknown.chain(&["missing"], vec![Filter::Fps("30".to_string())], &["v"]);
+    assert!(invalid(&unknown));
+    let mut twice = FilterGraph::new();
+    twice.chain(&["0:v"], vec![Filter::Split{ kind: StreamType::Video, outputs: 2 }], &["a", "b"])
+        .chain(&["a", "a"], vec![Filter::overlay(0, 0)], &["v"]);
+    assert!(invalid(&twice));
+    let mut mismatched = FilterGraph::new();
+    mismatched.chain(&["0:v"], vec![Filter::Volume("2".to_string())], &["a"]);
+    assert!(invalid(&mismatched));
+    let mut slow = FilterGraph::new();
+    slow.chain(&["1:a"], vec![Filter::ATempo(0.25)], &["a"]);
+    assert!(invalid(&slow));
+    let mut unconnected = FilterGraph::new();
+    unconnected.chain(&["0:v"], vec![Filter::Split{ kind: StreamType::Video, outputs: 2 }, Filter::Fps("30".to_string())], &["v"]);
+    assert!(invalid(&unconnected));
+
+    let mut ffmpeg = FFmpeg::new();
+    let mut graph = FilterGraph::new();
+    graph.chain(&["0:v"], vec![Filter::Split{ kind: StreamType::Video, outputs: 2 }], &["v0", "v1"]);
+    let result = ffmpeg.input("https://example.com/video").unwrap()
+        .output("clip.mp4").unwrap()
+        .filter_graph(graph)
+        .spawn();
+    assert!(matches!(result, Err(ClypperError::FilterGraph(_))));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs ffmpeg built with libass, run with `cargo test -- --ignored`"]
+fn test_filter_graph_escaping() -> Result<(), ClypperError>{
+    //ffmpeg has to read every escaped value back as it was, a missing file fails the run
+    let installation = FFmpegInstallation::discover()?;
+    assert!(installation.has_filter("subtitles"), "{} has no subtitles filter", installation.ffmpeg.display());
+    let dir = test_dir("filter_graph");
+    //Windows doesn't allow : in file names
+    let path = dir.join(if cfg!(windows) { "it's 3, [go]; gg.ass" } else { "it's 3:00, [go]; gg.ass" });
+    let captions = Captions{ cues: vec![extract::captions::Cue{ start_ms: 0, end_ms: 1_000, text: "gg".to_string(), words: vec![] }] };
+    std::fs::write(&path, download::subtitles::to_ass(&captions, &CaptionStyle::default(), (320, 180)))?;
+    let mut graph = FilterGraph::new();
+    graph.chain(&["0:v"], vec![Filter::Subtitles{ path, force_style: Some("Fontsize=24,Outline=2".to_string()) }], &["v"]);
+    let output = std::process::Command::new(&installation.ffmpeg)
+        .args(["-hide_banner", "-f", "lavfi", "-i", "color=size=320x180:duration=1", "-filter_complex", graph.render()?.as_str()])
+        .args(["-map", "[v]", "-f", "null", "-"])
+        .output()?;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    Ok(())
+}
+
+#[test]
+fn test_captions() -> Result<(), ClypperError>{
+    use extract::captions::find_track;
+    use download::subtitles::{to_ass, to_srt, to_vtt};
+
+    let captions_url = "https://www.youtube.com/api/timedtext?v=fXtUZmQ8dYc&ei=fixture&expire=1700000000&key=yt8&kind=asr&lang=en";
+    let client = FixtureClient::new()
+        .page(CLIP_URL, CLIP_PAGE)
+        .file(captions_url, "fixtures/youtube_captions.json3")?;
+    let extractor = YouTubeExtractor::with_client(client)?;
+    let clip = extractor.extract(CLIP_URL)?;
+    assert_eq!(clip.captions.len(), 3);
+    assert_eq!(find_track(&clip.captions, "en", true).map(|track| track.name.as_str()), Some("English"));
+    assert_eq!(find_track(&clip.captions, "de", false).map(|track| track.name.as_str()), Some("German"));
+    assert!(find_track(&clip.captions, "fr", true).is_none());
+    let auto = clip.captions.iter().find(|track| track.auto_generated).ok_or(ClypperError::MissingField("captionTracks"))?;
+    assert_eq!(auto.name, "English (auto-generated)");
+    assert!(matches!(extractor.captions(&clip.captions[0]), Err(ClypperError::HttpStatus(_, 404))));
+
+    let captions = extractor.captions(auto)?;
+    assert_eq!(captions.cues.len(), 5);
+    assert_eq!((captions.cues[0].end_ms, captions.cues[2].end_ms), (6_100, 12_000));
+    let trimmed = captions.trim(clip.time);
+    let texts: Vec<&str> = trimmed.cues.iter().map(|cue| cue.text.as_str()).collect();
+    assert_eq!(texts, ["go", "the jump nobody believed", "no way that worked", "chat is going crazy"]);
+    let crazy = &trimmed.cues[3].words[3];
+    assert_eq!((crazy.start_ms, crazy.end_ms), (8_100, 10_000));
+
+    let srt = to_srt(&trimmed);
+    assert!(srt.starts_with("1\n00:00:00,000 --> 00:00:01,100\ngo\n\n2\n00:00:01,100 --> 00:00:04,100\nthe jump nobody believed\n\n"));
+    assert!(to_vtt(&trimmed).starts_with("WEBVTT\n\n00:00:00.000 --> 00:00:01.100\ngo\n\n"));
+    let style = CaptionStyle{ color: "#ffcc00".parse()?, ..CaptionStyle::default() };
+    let ass = to_ass(&trimmed, &style, (1080, 1920));
+    assert!(ass.contains("PlayResX: 1080\nPlayResY: 1920\n"));
+    assert!(ass.contains("Style: Default,Arial,64,&H0000CCFF,&H0000CCFF,&H00000000,&H7F000000,-1,"));
+    assert!(ass.contains("Dialogue: 0,0:00:07.00,0:00:10.00,Default,,0,0,0,,chat is going crazy\n"));
+    assert!("red".parse::<Color>().is_err());
+
+    let mut ffmpeg = FFmpeg::new();
+    let result = ffmpeg.input("https://example.com/video").unwrap()
+        .output("clip.mp4").unwrap()
+        .cut_mode(CutMode::StreamCopy)
+        .burn_captions(trimmed, style)
+        .spawn();
+    assert!(matches!(result, Err(ClypperError::FFmpegUnsupported(_))));
+    Ok(())
+}
+
+#[test]
+fn test_karaoke(){
+    use extract::captions::{CaptionWord, Cue};
+    use download::subtitles::to_ass;
+
+    let word = |start_ms: u64, end_ms: u64, text: &str| CaptionWord{ start_ms, end_ms, text: text.to_string() };
+    let captions = Captions{ cues: vec![
+        Cue{ start_ms: 0, end_ms: 2_000, text: "no way that worked".to_string(), words: vec![word(0, 400, "no"), word(500, 900, "way"), word(900, 1_300, "that"), word(1_300, 2_000, "worked")] },
+        Cue{ start_ms: 2_000, end_ms: 3_000, text: "gg {ez}".to_string(), words: vec![] },
+    ]};
+    let karaoke = Karaoke{ highlight_scale: 115, uppercase: true, ..Karaoke::default() };
+    let style = CaptionStyle{ karaoke: Some(karaoke), ..CaptionStyle::default() };
+    let ass = to_ass(&captions, &style, (1080, 1920));
+    let dialogues: Vec<&str> = ass.lines().filter_map(|line| line.strip_prefix("Dialogue: 0,")).collect();
+    assert_eq!(dialogues, [
+        "0:00:00.00,0:00:00.50,Default,,0,0,0,,{\\c&H00DDFF&\\fscx115\\fscy115}NO{\\r} WAY THAT",
+        "0:00:00.50,0:00:00.90,Default,,0,0,0,,NO {\\c&H00DDFF&\\fscx115\\fscy115}WAY{\\r} THAT",
+        "0:00:00.90,0:00:01.30,Default,,0,0,0,,NO WAY {\\c&H00DDFF&\\fscx115\\fscy115}THAT{\\r}",
+        "0:00:01.30,0:00:02.00,Default,,0,0,0,,{\\c&H00DDFF&\\fscx115\\fscy115}WORKED{\\r}",
+        "0:00:02.00,0:00:02.33,Default,,0,0,0,,{\\c&H00DDFF&\\fscx115\\fscy115}GG{\\r} (EZ)",
+        "0:00:02.33,0:00:03.00,Default,,0,0,0,,GG {\\c&H00DDFF&\\fscx115\\fscy115}(EZ){\\r}",
+    ]);
+
+    let style: CaptionStyle = serde_json::from_str(r##"{"font": "Montserrat Black", "position": "middle", "karaoke": {"highlight_color": "#00ff00", "max_words_per_line": 2}}"##).unwrap();
+    let karaoke = style.karaoke.clone().unwrap();
+    assert_eq!((karaoke.highlight_color, karaoke.max_words_per_line, style.font_size), (Color::rgb(0, 255, 0), 2, 64));
+    let ass = to_ass(&captions, &style, (1080, 1920));
+    assert!(ass.contains("Style: Default,Montserrat Black,64,"));
+    assert!(ass.contains("Dialogue: 0,0:00:00.90,0:00:01.30,Default,,0,0,0,,{\\c&H00FF00&}that{\\r} worked\n"));
+}
+
+#[test]
+fn test_caption_words(){
+    use extract::captions::CaptionWord;
+
+    let word = |start_ms: u64, end_ms: u64, text: &str| CaptionWord{ start_ms, end_ms, text: text.to_string() };
+    let words = vec![
+        word(0, 300, " wait"), word(300, 600, "what?"), word(600, 900, "he"), word(900, 1_200, "actually"),
+        word(1_200, 1_500, "hit"), word(3_000, 3_400, "that"), word(3_400, 3_600, " "), word(3_600, 4_000, "shot"),
+    ];
+    let captions = Captions::from_words(words, 3);
+    let cues: Vec<(u64, u64, &str)> = captions.cues.iter().map(|cue| (cue.start_ms, cue.end_ms, cue.text.as_str())).collect();
+    //Ends on the question mark, the three word limit and the pause before "that"
+    assert_eq!(cues, [(0, 600, "wait what?"), (600, 1_500, "he actually hit"), (3_000, 4_000, "that shot")]);
+    assert_eq!(captions.cues[0].words[0].text, "wait");
+}
+
+#[cfg(feature = "whisper")]
+#[test]
+fn test_transcribe_words(){
+    use download::transcribe::segment_word;
+
+    let word = segment_word(" actually ", 90, 135).unwrap();
+    assert_eq!((word.start_ms, word.end_ms, word.text.as_str()), (900, 1_350, "actually"));
+    //Whisper sometimes ends a word before it starts, or before the audio does
+    let word = segment_word("hit", -5, -10).unwrap();
+    assert_eq!((word.start_ms, word.end_ms), (0, 0));
+    assert!(segment_word("[BLANK_AUDIO]", 0, 100).is_none());
+    assert!(segment_word("  ", 0, 100).is_none());
+}
+
+#[test]
+fn test_loudness() -> Result<(), ClypperError>{
+    //What a measure pass writes to stderr, progress and all
+    let mut parser = download::loudness::LoudnessReportParser::new();
+    let captured = include_str!("../fixtures/ffmpeg/loudnorm_measure.txt").lines()
+        .filter(|line| !download::progress::ProgressParser::is_progress_line(line))
+        .filter_map(|line| parser.feed(line))
+        .collect::<Result<Vec<LoudnessReport>, ClypperError>>()?;
+    assert_eq!(captured.len(), 1);
+    assert_eq!((captured[0].input.integrated, captured[0].input.threshold, captured[0].target_offset), (-21.37, -31.61, 0.44));
+
+    let stderr = "[Parsed_loudnorm_0 @ 0x55d0c4f0a2c0] \n{\n\t\"input_i\" : \"-27.61\",\n\t\"input_tp\" : \"-4.47\",\n\t\"input_lra\" : \"18.06\",\n\t\"input_thresh\" : \"-39.20\",\n\t\"output_i\" : \"-14.02\",\n\t\"output_tp\" : \"-1.00\",\n\t\"output_lra\" : \"9.80\",\n\t\"output_thresh\" : \"-24.44\",\n\t\"normalization_type\" : \"dynamic\",\n\t\"target_offset\" : \"0.02\"\n}\n[out#0/null @ 0x55d0c4f09a80] video:0KiB audio:4500KiB";
+    let report = LoudnessReport::parse(stderr.lines()).unwrap()?;
+    assert_eq!((report.input.integrated, report.input.range, report.output.true_peak), (-27.61, 18.06, -1.0));
+    assert!(!report.linear);
+    assert!(LoudnessReport::parse(["frame=1", "Stream mapping:"]).is_none());
+    //Warnings after the report push it out of the error tail, the parser reads the whole stream
+    let warnings: Vec<String> = (0..40).map(|i| format!("[aac @ 0x55d0c4f0b100] Queue input is backward in time {}", i)).collect();
+    let mut diagnostics = download::diagnostics::Diagnostics::new(vec![], "");
+    let mut parser = download::loudness::LoudnessReportParser::new();
+    let mut streamed = None;
+    for line in stderr.lines().chain(warnings.iter().map(String::as_str)){
+        streamed = parser.feed(line).or(streamed);
+        diagnostics.push(line);
+    }
+    assert!(LoudnessReport::parse(diagnostics.tail()).is_none());
+    assert_eq!(streamed.unwrap()?, report);
+
+    let mut graph = FilterGraph::new();
+    graph.chain(&["1:a"], vec![Filter::Loudnorm{ target: LoudnessTarget::STREAMING, measured: Some(report) }], &["a"]);
+    assert_eq!(
+        graph.render()?,
+        "[1:a]loudnorm=I=-14:TP=-1:LRA=11:measured_I=-27.61:measured_TP=-4.47:measured_LRA=18.06:measured_thresh=-39.2:offset=0.02:linear=true:print_format=json[a]",
+    );
+    //Silence can't be measured, so it's normalised in one pass
+    let silence = "{\n\"input_i\" : \"-inf\",\n\"input_tp\" : \"-inf\",\n\"input_lra\" : \"0.00\",\n\"input_thresh\" : \"-inf\",\n\"output_i\" : \"-inf\",\n\"output_tp\" : \"-inf\",\n\"output_lra\" : \"0.00\",\n\"output_thresh\" : \"-inf\",\n\"normalization_type\" : \"dynamic\",\n\"target_offset\" : \"inf\"\n}";
+    let silence = LoudnessReport::parse(silence.lines()).unwrap()?;
+    let mut graph = FilterGraph::new();
+    graph.chain(&["1:a"], vec![Filter::Loudnorm{ target: LoudnessTarget::PODCAST, measured: Some(silence) }], &["a"]);
+    assert_eq!(graph.render()?, "[1:a]loudnorm=I=-16:TP=-1.5:LRA=11:print_format=json[a]");
+
+    let mut graph = FilterGraph::new();
+    graph.chain(&["1:a"], vec![Filter::Loudnorm{ target: LoudnessTarget::new(-3.0, -1.0, 11.0), measured: None }], &["a"]);
+    assert!(matches!(graph.validate(), Err(ClypperError::FilterGraph(_))));
+    assert_eq!(LoudnessTarget::preset("broadcast"), Some(LoudnessTarget::BROADCAST));
+
+    //It's measured on the source audio, so a graph can't filter the audio first
+    let mut graph = FilterGraph::new();
+    graph.chain(&["1:a"], vec![Filter::Volume("2".to_string())], &["a"]);
+    let mut ffmpeg = FFmpeg::new();
+    let result = ffmpeg.input("https://example.com/video")?
+        .input("https://example.com/audio")?
+        .output("clip.mp4")?
+        .filter_graph(graph)
+        .normalize_loudness(LoudnessTarget::STREAMING)
+        .spawn();
+    assert!(matches!(result, Err(ClypperError::FFmpegUnsupported(_))));
+    //The audio is measured from `1:a`, which a lone muxed input doesn't have
+    let mut ffmpeg = FFmpeg::new();
+    let result = ffmpeg.input("https://example.com/muxed")?
+        .output("clip.mp4")?
+        .normalize_loudness(LoudnessTarget::STREAMING)
+        .spawn();
+    assert!(matches!(result, Err(ClypperError::FFmpeg(message)) if message.contains("1:a")));
+    Ok(())
+}
+
+#[test]
+fn test_cut_points(){
+    use download::{cut::{parse_keyframes, CutPoints}, progress::ProgressParser};
+
+    let keyframes = parse_keyframes("0.000000\n2.002000,\nN/A\n4.004000\n6.006000\n8.008000\n");
+    assert_eq!(keyframes, [0, 2_002_000, 4_004_000, 6_006_000, 8_008_000]);
+    assert_eq!(
+        CutPoints::find(&keyframes, 1_000, 7_000),
+        Some(CutPoints{ first_keyframe_us: 2_002_000, last_keyframe_us: 6_006_000 }),
+    );
+    //A cut starting on a keyframe has no head to encode
+    assert_eq!(CutPoints::find(&keyframes, 2_002, 7_000).map(|points| points.first_keyframe_us), Some(2_002_000));
+    assert_eq!(CutPoints::find(&keyframes, 2_500, 5_000), None);
+    assert_eq!(CutPoints::find(&[], 0, 5_000), None);
+
+    //The tail of a smart cut reports progress from where it starts in the clip
+    let mut parser = ProgressParser::new(6_000).partial(5_006);
+    parser.feed("out_time_us=994000");
+    let report = parser.feed("progress=end").unwrap();
+    assert_eq!((report.out_time_ms, report.end, report.percent), (6_000, false, Some(100.0)));
+}
+
+#[test]
+fn test_diagnostics(){
+    use download::diagnostics::Diagnostics;
+
+    let video = "https://rr1.googlevideo.com/videoplayback?itag=137";
+    let audio = "https://rr1.googlevideo.com/videoplayback?itag=140";
+    let classify = |lines: &[&str]| {
+        let mut diagnostics = Diagnostics::new(vec![video.to_string(), audio.to_string()], "/clips/out.mp4");
+        lines.iter().for_each(|line| diagnostics.push(*line));
+        diagnostics.failure().cloned()
+    };
+    //The first line doesn't say which input was refused, the second does
+    assert_eq!(
+        classify(&["[https @ 0x55d1] HTTP error 403 Forbidden", &format!("{}: Server returned 403 Forbidden (access denied)", audio)]),
+        Some(FFmpegFailure::InputForbidden(Some(audio.to_string()))),
+    );
+    assert_eq!(classify(&["[https @ 0x55d1] HTTP error 403 Forbidden"]), Some(FFmpegFailure::InputForbidden(None)));
+    assert_eq!(classify(&[&format!("{}: Server returned 404 Not Found", audio)]), Some(FFmpegFailure::InputNotFound(Some(audio.to_string()))));
+    assert_eq!(classify(&["Unknown encoder 'libfdk_aac'"]), Some(FFmpegFailure::UnknownEncoder("libfdk_aac".to_string())));
+    assert_eq!(classify(&["av_interleaved_write_frame(): No space left on device"]), Some(FFmpegFailure::DiskFull));
+    assert!(matches!(classify(&["/clips/out.mp4: No such file or directory"]), Some(FFmpegFailure::InvalidOutput(_))));
+    assert!(matches!(
+        classify(&["[out#0/mp4 @ 0x55d1] Error opening output /clips/out.mp4: No such file or directory"]),
+        Some(FFmpegFailure::InvalidOutput(_)),
+    ));
+    assert!(matches!(classify(&["Error opening output file /clips/out.mp4."]), Some(FFmpegFailure::InvalidOutput(_))));
+    assert_eq!(classify(&[&format!("{}: No such file or directory", video)]), None);
+
+    //With two inputs and no url on the line, the retry has to blame both
+    let mut diagnostics = Diagnostics::new(vec![video.to_string(), audio.to_string()], "/clips/out.mp4");
+    diagnostics.push("[https @ 0x55d1] HTTP error 403 Forbidden");
+    assert_eq!(diagnostics.forbidden_input(), Some(format!("{}, {}", video, audio)));
+    let mut diagnostics = Diagnostics::new(vec![video.to_string()], "/clips/out.mp4");
+    diagnostics.push("[https @ 0x55d1] HTTP error 403 Forbidden");
+    assert_eq!(diagnostics.forbidden_input(), Some(video.to_string()));
+
+    let mut diagnostics = Diagnostics::new(vec![], "out.mp4");
+    (0..100).for_each(|i| diagnostics.push(format!("line {}", i)));
+    assert_eq!(diagnostics.tail().count(), 32);
+    assert_eq!(diagnostics.tail().last(), Some("line 99"));
+}
+
+#[test]
+fn test_ffmpeg_installation() -> Result<(), ClypperError>{
+    use download::installation::{EncoderKind, FFmpegInstallation};
+
+    let mut installation = FFmpegInstallation::parse(
+        "/usr/bin/ffmpeg".into(),
+        include_str!("../fixtures/ffmpeg/version.txt"),
+        include_str!("../fixtures/ffmpeg/encoders.txt"),
+        include_str!("../fixtures/ffmpeg/filters.txt"),
+        include_str!("../fixtures/ffmpeg/protocols.txt"),
+    )?;
+    assert_eq!((installation.version.as_str(), installation.major_version()), ("6.1.1-3ubuntu5", Some(6)));
+    assert_eq!(installation.encoders.len(), 13);
+    assert_eq!(installation.encoder("libopus").map(|encoder| encoder.kind), Some(EncoderKind::Audio));
+    assert!(installation.has_filter("loudnorm") && installation.has_filter("overlay") && installation.has_filter("color"));
+    assert!(!installation.has_filter("Timeline"));
+    assert!(installation.can_read("https") && !installation.can_read("rtmp"));
+    assert!(!installation.output_protocols.contains("hls"));
+
+    let build = |installation: &FFmpegInstallation, input: &str| {
+        let mut ffmpeg = FFmpeg::new();
+        ffmpeg.installation(std::sync::Arc::new(installation.clone()));
+        ffmpeg.time(0, 1000)?.input(input)?.input(input)?.output("never.mp4")?;
+        Ok::<FFmpeg, ClypperError>(ffmpeg)
+    };
+    let input = "https://production.assets.clips.twitchcdn.net/v2/media/fixture/720.mp4";
+    assert!(matches!(build(&installation, "rtmp://live.example.com/app")?.spawn(), Err(ClypperError::FFmpegUnsupported(_))));
+    assert!(matches!(build(&installation, input)?.video_codec("h264_nvenc").spawn(), Err(ClypperError::FFmpegUnsupported(_))));
+
+    //Without libx264 the default falls back to another H.264 encoder, without any it fails early
+    installation.encoders.retain(|encoder| encoder.name != "libx264");
+    assert_eq!(installation.pick_encoder(&["libx264", "libopenh264", "h264_vaapi"])?, "h264_vaapi");
+    installation.encoders.retain(|encoder| encoder.kind != EncoderKind::Video);
+    assert!(matches!(build(&installation, input)?.spawn(), Err(ClypperError::FFmpegUnsupported(_))));
+    Ok(())
+}
+
+#[test]
+fn test_verification() -> Result<(), ClypperError>{
+    use download::verify::{ProbeOutput, VerificationProblem};
+
+    let probe: ProbeOutput = serde_json::from_str(include_str!("../fixtures/ffmpeg/ffprobe.json"))
+        .map_err(|err| ClypperError::InvalidField("ffprobe", err.to_string()))?;
+    let report = Verification::new(10_000).video_codec("h264").audio_codec("aac").resolution(1920, 1080).check(&probe);
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.duration_ms, Some(10_008));
+
+    let report = Verification::new(15_000).video_codec("hevc").resolution(1080, 1920).check(&probe);
+    assert_eq!(report.problems, vec![
+        VerificationProblem::VideoCodec{ expected: "hevc".to_string(), actual: "h264".to_string() },
+        VerificationProblem::Resolution{ expected: (1080, 1920), actual: (1920, 1080) },
+        VerificationProblem::Duration{ expected_ms: 15_000, actual_ms: Some(10_008) },
+    ]);
+    assert_eq!(
+        ClypperError::VerificationFailed(Box::new(report)).to_string(),
+        "output failed verification: expected hevc video, got h264, expected 1080x1920, got 1920x1080, expected 15000ms long, got 10008ms",
+    );
+
+    let audio_only = ProbeOutput{ streams: probe.streams[1..].to_vec(), format: None };
+    let report = Verification::new(10_000).check(&audio_only);
+    assert_eq!(report.problems, vec![VerificationProblem::MissingVideo]);
+    assert!(Verification::new(10_000).audio_only().check(&audio_only).is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_encode_profiles() -> Result<(), ClypperError>{
+    use download::profile::{codec_name, VideoQuality};
+
+    let default = EncodeProfile::default();
+    assert_eq!(default.video_args("libx264"), ["-c:v", "libx264", "-crf", "23", "-preset", "medium", "-pix_fmt", "yuv420p"]);
+    //Rate control and presets are specific to the profile's own encoder
+    assert_eq!(default.video_args("h264_nvenc"), ["-c:v", "h264_nvenc", "-pix_fmt", "yuv420p"]);
+    assert_eq!(default.audio_args("aac"), ["-c:a", "aac", "-b:a", "160k"]);
+    assert_eq!(default.container_args(), ["-f", "mp4", "-movflags", "+faststart"]);
+
+    let web = EncodeProfile::builtin("web").unwrap();
+    assert_eq!(web.video_args("libvpx-vp9"), ["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0", "-pix_fmt", "yuv420p"]);
+    assert_eq!(web.container_args(), ["-f", "webm"]);
+    assert_eq!(EncodeProfile::builtin("archive").unwrap().container_args(), ["-f", "matroska"]);
+    assert_eq!((codec_name(web.video_codec.as_str()), codec_name(web.audio_codec.as_str())), (Some("vp9"), Some("opus")));
+    assert_eq!(codec_name("hevc_videotoolbox"), Some("hevc"));
+
+    assert_eq!(Container::from_path(std::path::Path::new("clip.MKV")), Some(Container::Mkv));
+    assert_eq!(Container::from_path(std::path::Path::new("clip.ts")), None);
+
+    let profiles = EncodeProfiles::from_json(r#"[
+        {"name": "discord", "quality": {"bitrate_kbps": 2000}, "preset": "fast", "audio_bitrate_kbps": 96},
+        {"name": "small", "video_codec": "libx265", "quality": {"crf": 30}, "container": "mkv"}
+    ]"#)?;
+    let discord = profiles.get("discord").unwrap();
+    assert_eq!((discord.video_codec.as_str(), discord.audio_codec.as_str(), discord.container), ("libx264", "aac", Container::Mp4));
+    assert_eq!(discord.video_args("libx264"), ["-c:v", "libx264", "-b:v", "2000k", "-preset", "fast"]);
+    let small = profiles.get("small").unwrap();
+    assert_eq!((small.quality, small.container), (Some(VideoQuality::Crf(30)), Container::Mkv));
+    assert_eq!(profiles.iter().count(), EncodeProfile::builtins().len() + 1);
+    assert!(matches!(EncodeProfiles::from_json(r#"[{"name": "bad", "container": "avi"}]"#), Err(ClypperError::InvalidField("profiles", _))));
+
+    let mut ffmpeg = FFmpeg::new();
+    let result = ffmpeg.input("https://example.com/video")?
+        .output("clip.mp4")?
+        .profile(web)
+        .spawn();
+    assert!(matches!(result, Err(ClypperError::FFmpeg(message)) if message.contains("webm")));
+    Ok(())
+}
+
+#[test]
+fn test_fixture_server() -> Result<(), ClypperError>{
+    use extract::http::{CurlClient, HttpClient, HttpRequest, HttpResponse};
+
+    let server = FixtureServer::start()?;
+    server.route("/clip/UgkxFixtureClip0000000000000000000", HttpResponse::ok(CLIP_PAGE));
+    server.route("/bytes", HttpResponse::ok("0123456789"));
+    let extractor = YouTubeExtractor::with_client(CurlClient::new())?;
+    let url = server.url("/clip/UgkxFixtureClip0000000000000000000");
+    let clip = extractor.extract(url.as_str())?;
+    assert_eq!((clip.time.0, clip.time.1), (5000, 15000));
+
+    let range = HttpRequest::get(server.url("/bytes")).header("Range", "bytes=2-5");
+    let response = extractor.client().fetch(&range)?;
+    assert_eq!((response.status, response.text()?.as_str()), (206, "2345"));
+    Ok(())
+}
+
+#[test]
+fn test_twitch_extractor() -> Result<(), ClypperError>{
+    use extract::{http::{CurlClient, HttpResponse}, twitch::TwitchExtractor};
+
+    let server = FixtureServer::start()?;
+    server.route("/gql", HttpResponse::ok(include_str!("../fixtures/twitch_clip_gql.json")));
+    let extractor = TwitchExtractor::with_client(CurlClient::new()).endpoint(server.url("/gql"));
+    for url in ["https://clips.twitch.tv/FixtureCleverOtterKappa-x9Gz3Lp0qRst4UvW", "https://www.twitch.tv/fixturestreamer/clip/FixtureCleverOtterKappa-x9Gz3Lp0qRst4UvW?filter=clips"]{
+        assert!(extractor.matches(url));
+        let clip = extractor.extract(url)?;
+        assert_eq!(clip.formats.iter().count(), 4);
+        assert!(clip.formats.iter().all(|format| format.width.is_none() && format.resolution() == format.height.unwrap_or(0)));
+        assert_eq!((clip.time.0, clip.time.1), (0, 28_000));
+        assert_eq!(clip.source_time.map(|time| (time.0, time.1)), Some((5_412_000, 5_440_000)));
+        assert!(clip.resource.0.starts_with("https://production.assets.clips.twitchcdn.net/v2/media/fixture/1080.mp4?sig="));
+        assert_eq!(clip.resource.0, clip.resource.1);
+        assert_eq!(clip.metadata.title.as_deref(), Some("the jump nobody believed"));
+        assert_eq!(clip.metadata.creator.as_deref(), Some("ClipFan"));
+        assert_eq!(clip.metadata.channel_name.as_deref(), Some("FixtureStreamer"));
+        assert_eq!(clip.metadata.source_title.as_deref(), Some("day 3 of the blind run"));
+        assert_eq!(clip.metadata.source_duration_ms, Some(19_845_000));
+    }
+    assert!(!extractor.matches("https://www.twitch.tv/fixturestreamer"));
+    Ok(())
+}
+
+#[test]
+fn test_format_selector(){
+    let stream = |itag: u32, mime_type: &str, width: u32, height: u32| {
+        let (container, codec) = Format::parse_mime_type(mime_type);
+        Format{ itag, url: format!("https://example.com/{}", itag), container, codec, width: Some(width), height: Some(height), ..Format::default() }
+    };
+    let audio_stream = |itag: u32, mime_type: &str, bitrate: u64| {
+        let (container, codec) = Format::parse_mime_type(mime_type);
+        Format{ itag, url: format!("https://example.com/{}", itag), container, codec, bitrate: Some(bitrate), audio_channels: Some(2), ..Format::default() }
+    };
+    let formats = FormatList(vec![
+        stream(137, r#"video/mp4; codecs="avc1.640028""#, 1920, 1080),
+        stream(248, r#"video/webm; codecs="vp9""#, 1920, 1080),
+        stream(136, r#"video/mp4; codecs="avc1.4d401f""#, 1280, 720),
+        stream(313, r#"video/webm; codecs="vp9""#, 3840, 2160),
+        audio_stream(140, r#"audio/mp4; codecs="mp4a.40.2""#, 130_000),
+        audio_stream(251, r#"audio/webm; codecs="opus""#, 160_000),
+    ]);
+
+    let (video, audio) = formats.select(&FormatSelector::default()).unwrap();
+    assert_eq!((video.itag, audio.itag), (137, 140));
+    let (video, audio) = formats.select(&FormatSelector::best()).unwrap();
+    assert_eq!((video.itag, audio.itag), (313, 251));
+    let (video, _) = formats.select(&FormatSelector::smallest()).unwrap();
+    assert_eq!(video.itag, 136);
+    let (video, _) = formats.select(&FormatSelector::itag(248)).unwrap();
+    assert_eq!(video.itag, 248);
+    assert!(formats.select(&FormatSelector::itag(22)).is_err());
+
+    let vertical = FormatList(vec![stream(137, r#"video/mp4; codecs="avc1.640028""#, 1080, 1920), audio_stream(140, r#"audio/mp4; codecs="mp4a.40.2""#, 130_000)]);
+    let (video, _) = vertical.select(&FormatSelector::default()).unwrap();
+    assert_eq!(video.itag, 137);
 
+    //A preferred codec at a lower resolution loses to the best one under the cap
+    let mixed = FormatList(vec![
+        stream(18, r#"video/mp4; codecs="avc1.42001E""#, 640, 360),
+        stream(248, r#"video/webm; codecs="vp9""#, 1920, 1080),
+        stream(313, r#"video/webm; codecs="vp9""#, 3840, 2160),
+        audio_stream(140, r#"audio/mp4; codecs="mp4a.40.2""#, 130_000),
+    ]);
+    let (video, _) = mixed.select(&FormatSelector::default()).unwrap();
+    assert_eq!(video.itag, 248);
+    let (video, _) = mixed.select(&FormatSelector::smallest().prefer_codec("vp9")).unwrap();
+    assert_eq!(video.itag, 18);
+}
+
+#[test]
+fn test_player_page() -> Result<(), ClypperError>{
+    use extract::player::PlayerPage;
+
+    let html = r#"<script>var ytInitialPlayerResponse = {
+        "streamingData": {"adaptiveFormats": [
+            {"url": "https://rr1.googlevideo.com/videoplayback?expire=1700000000\u0026itag=137", "mimeType": "video/mp4; codecs=\"avc1.640028\"", "itag": 137, "height": 1080, "width": 1920},
+            {"itag": 140, "audioSampleRate": "44100", "mimeType": "audio/mp4; codecs=\"mp4a.40.2\"", "audioChannels": 2, "url": "https:\/\/rr1.googlevideo.com\/videoplayback?itag=140"}
+        ]},
+        "playabilityStatus": {"status": "OK"}
+    };var meta = document.createElement('meta');</script>
+    <script>window["ytInitialData"] = {"engagementPanels": [{"clipConfig": {"endTimeMs": "15000", "postId": "Ugkx", "startTimeMs": "5000"}}]};</script>"#;
+    let page = PlayerPage::parse(html)?;
+    page.check_playable()?;
+    let formats = FormatList(page.formats());
+    let (video, audio) = formats.select(&FormatSelector::default())?;
+    assert_eq!(video.url, "https://rr1.googlevideo.com/videoplayback?expire=1700000000&itag=137");
+    assert_eq!(audio.url, "https://rr1.googlevideo.com/videoplayback?itag=140");
+    assert_eq!(audio.sample_rate, Some(44100));
+    let config = page.clip_config().ok_or(ClypperError::MissingField("clipConfig"))?;
+    assert_eq!((config.start_time_ms, config.end_time_ms), (Some(5000), Some(15000)));
+    //A config missing a time still parses, so the extractor can say which one is missing
+    let page = PlayerPage::parse(html.replace(r#""endTimeMs": "15000", "#, "").as_str())?;
+    let config = page.clip_config().ok_or(ClypperError::MissingField("clipConfig"))?;
+    assert_eq!((config.start_time_ms, config.end_time_ms), (Some(5000), None));
+    let no_start = CLIP_PAGE.replace(r#""startTimeMs":"5000","#, "");
+    let extractor = YouTubeExtractor::with_client(FixtureClient::new().page(CLIP_URL, no_start))?;
+    assert!(matches!(extractor.extract(CLIP_URL), Err(ClypperError::MissingField("startTimeMs"))));
+
+    let unavailable = r#"var ytInitialPlayerResponse = {"playabilityStatus": {"status": "LOGIN_REQUIRED", "reason": "This video is private"}};"#;
+    assert!(matches!(PlayerPage::parse(unavailable)?.check_playable(), Err(ClypperError::ClipUnavailable(_))));
     Ok(())
 }
 
 #[test]
+#[ignore = "needs ffmpeg, run with `cargo test -- --ignored`"]
 fn test_downloader() -> Result<(), ClypperError>{
-    let url = "https://www.youtube.com/clip/UgkxWw82JHM2Y6ZFPbT9lIVAgOzgbWEl-ocz";
-    let extractor = Extractor::new().unwrap();
-    let clip = match extractor.extract(url){
+    let dir = test_dir("downloader");
+    let (_server, extractor) = fixture_media(&dir)?;
+    let clip = match extractor.extract(CLIP_URL){
         Ok(clip) => clip,
         Err(err) => {
             println!("{:?}", err);
@@ -38,35 +939,37 @@ fn test_downloader() -> Result<(), ClypperError>{
         }
     };
     
-    let mut downloader = Downloader::new(clip, String::from("test.mp4"));
+    let downloader = Downloader::new(clip, dir.join("test.mp4").to_string_lossy().into_owned());
     downloader.download()?;
     Ok(())
 }
 
 #[test]
-fn test_ffmpeg() -> Result<(), FFmpegError>{
-    let url = "https://www.youtube.com/clip/UgkxWw82JHM2Y6ZFPbT9lIVAgOzgbWEl-ocz";
-    let extractor = Extractor::new().unwrap();
-    let clip = match extractor.extract(url){
+#[ignore = "needs ffmpeg, run with `cargo test -- --ignored`"]
+fn test_ffmpeg() -> Result<(), ClypperError>{
+    let dir = test_dir("ffmpeg");
+    let (_server, extractor) = fixture_media(&dir)?;
+    let clip = match extractor.extract(CLIP_URL){
         Ok(clip) => clip,
         Err(err) => {
             println!("{:?}", err);
-            return Err(FFmpegError{ message: "Failed to extract clip info".to_string(), state: FFmpegState::NotStarted });
+            return Err(err);
         }
     };
     
-    println!("{:?}", clip.time.clone());
+    println!("{:?}", clip.time);
     let total = clip.time.1 - clip.time.0;
     let pb = ProgressBar::new(total)
         .with_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:100.cyan/blue} {msg} {pos}/{len} ({eta})")
         .unwrap());
     let pb_cb = pb.clone();
     let pb_cb2 = pb.clone();
+    let output = dir.join("test.mp4").to_string_lossy().into_owned();
     let mut ffmpeg = FFmpeg::new();
-    ffmpeg.time(clip.time.0, clip.time.1)?
+    let output = ffmpeg.time(clip.time.0, clip.time.1)?
         .input(&clip.resource.0)?
         .input(&clip.resource.1)?
-        .output("test.mp4")?
+        .output(&output)?
         .state_change_callback(move |state|{
             match state{
                 FFmpegState::Starting => pb_cb2.set_message("FFmpeg starting..."),
@@ -75,14 +978,78 @@ fn test_ffmpeg() -> Result<(), FFmpegError>{
                 _ => {}
             }
         })
-        .progress_callback(move |time|{
+        .progress_callback(move |report|{
             pb_cb.set_message("Downloading...");
-            pb_cb.set_position(time);
+            pb_cb.set_position(report.out_time_ms);
         })
-        .spawn()?;
+        .verify(Verification::new(total).video_codec("h264").audio_codec("aac"))
+        .spawn()?
+        .wait()?;
 
     pb.finish();
+    assert!(output.path.exists());
+    assert!(output.verification.is_some_and(|report| report.is_ok()));
     
-    
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs ffmpeg, run with `cargo test -- --ignored`"]
+fn test_ffmpeg_cut_modes() -> Result<(), ClypperError>{
+    let dir = test_dir("cut_modes");
+    let (_server, extractor) = fixture_media(&dir)?;
+    let clip = extractor.extract(CLIP_URL)?;
+    let total = clip.time.duration_ms();
+    let encode = |name: &str, cut_mode: CutMode, loudness: Option<LoudnessTarget>| -> Result<FFmpegOutput, ClypperError>{
+        let mut ffmpeg = FFmpeg::new();
+        ffmpeg.clip(&clip)?.output(dir.join(name).to_string_lossy())?.cut_mode(cut_mode);
+        if let Some(target) = loudness{
+            ffmpeg.normalize_loudness(target);
+        }
+        ffmpeg.spawn()?.wait()
+    };
+
+    let copied = encode("copy.mp4", CutMode::StreamCopy, None)?;
+    //Copies start on the keyframe before the clip, so they can run a little long
+    let report = Verification::new(total).verify(&download::installation::default_ffprobe(), &copied.path)?;
+    assert!(report.duration_ms.is_some_and(|duration| duration.abs_diff(total) <= 2_000), "{:?}", report);
+    let smart = encode("smart.mp4", CutMode::SmartCut, None)?;
+    let report = Verification::new(total).video_codec("h264").audio_codec("aac").verify(&download::installation::default_ffprobe(), &smart.path)?;
+    assert!(report.is_ok());
+
+    let normalized = encode("loud.mp4", CutMode::Reencode, Some(LoudnessTarget::STREAMING))?;
+    let loudness = normalized.loudness.ok_or(ClypperError::MissingField("loudness"))?;
+    assert!((loudness.output.integrated - LoudnessTarget::STREAMING.integrated).abs() < 1.0, "{:?}", loudness);
+    assert!(loudness.output.true_peak <= LoudnessTarget::STREAMING.true_peak + 0.5, "{:?}", loudness);
+    Ok(())
+}
+
+#[test]
+fn test_clypper() -> Result<(), ClypperError>{
+    //Failing before ffmpeg starts still ends the job in the error state
+    let clypper = Clypper::with_registry(ExtractorRegistry::new());
+    let job = clypper.submit(ClipRequest::new("https://example.com/clip/abc", "clip.mp4"));
+    let control = job.control();
+    assert!(matches!(job.wait(), Err(ClypperError::UnsupportedUrl(_))));
+    assert_eq!(control.status(), FFmpegState::Error);
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs ffmpeg, run with `cargo test -- --ignored`"]
+fn test_clypper_download() -> Result<(), ClypperError>{
+    let dir = test_dir("clypper");
+    let (_server, extractor) = fixture_media(&dir)?;
+    let mut registry = ExtractorRegistry::new();
+    registry.register(extractor);
+    let clypper = Clypper::with_registry(registry);
+    //A directory gets a file named from the clip's metadata
+    let job = clypper.submit(ClipRequest::new(CLIP_URL, dir.as_path()).verify(0));
+    let control = job.control();
+    let output = job.wait()?;
+    assert_eq!(output.path, dir.join("Fixture Streamer - the jump nobody believed.mp4"));
+    assert!(output.path.exists());
+    assert!(output.verification.is_some_and(|report| report.is_ok()));
+    assert_eq!(control.status(), FFmpegState::Finished);
     Ok(())
 }